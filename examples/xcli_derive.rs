@@ -0,0 +1,84 @@
+//! Commands defined by types.
+//!
+//! ```text
+//! cargo run --example xcli_derive --features derive
+//! ```
+
+use xcli::*;
+
+/// Commands of ports.
+#[derive(XcliCommand)]
+enum Port {
+    /// shows a port
+    ///
+    /// The counters are shown with --verbose.
+    Show {
+        /// the port id
+        #[arg]
+        id: u32,
+        /// shows the counters as well
+        #[flag]
+        verbose: bool,
+    },
+    /// sets the description of ports
+    #[xcli(alias = "desc")]
+    Description {
+        /// the description, empty to remove it
+        #[opt]
+        text: Option<String>,
+        /// the port ids
+        ids: Vec<u32>,
+    },
+}
+
+impl Execute for Port {
+    fn execute(self, _app: &App) -> XcliResult {
+        match self {
+            Port::Show { id, verbose } => {
+                xprintln!("port {}: up", id);
+                if verbose {
+                    xprintln!("  rx 1024, tx 2048");
+                }
+            }
+            Port::Description { text, ids } => {
+                for id in ids {
+                    xprintln!("port {}: {}", id, text.as_deref().unwrap_or("<none>"));
+                }
+            }
+        }
+        Ok(CmdExeCode::Ok)
+    }
+}
+
+#[derive(XcliCommand)]
+enum Device {
+    /// manages the ports
+    Port(Port),
+    /// pings a host
+    Ping {
+        /// the host to ping
+        host: String,
+        /// the number of packets, 3 by default
+        count: Option<u32>,
+    },
+}
+
+impl Execute for Device {
+    fn execute(self, app: &App) -> XcliResult {
+        match self {
+            Device::Port(port) => port.execute(app),
+            Device::Ping { host, count } => {
+                for i in 0..count.unwrap_or(3) {
+                    xprintln!("reply from {}: seq={}", host, i);
+                }
+                Ok(CmdExeCode::Ok)
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut app = App::new("xCLI").version("v0.1").author("kingwel.xie@139.com");
+    app.add_commands::<Device>();
+    app.run();
+}
@@ -0,0 +1,47 @@
+//! Generate the reference document of a CLI.
+//!
+//! ```text
+//! cargo run --example xcli_doc -- man > xcli.1
+//! cargo run --example xcli_doc -- markdown > xcli.md
+//! cargo run --example xcli_doc -- html > xcli.html
+//! ```
+
+use xcli::*;
+
+fn main() {
+    let format = match std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "markdown".to_string())
+        .parse::<DocFormat>()
+    {
+        Ok(format) => format,
+        Err(err) => {
+            eprintln!("{}\nUsage: xcli_doc [man|markdown|html]", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut app = App::new("xCLI").version("v0.1").author("kingwel.xie@139.com");
+
+    app.add_subcommand(
+        Command::new("show")
+            .about("shows the status of the device")
+            .subcommand(
+                Command::new("ports")
+                    .about("shows the ports")
+                    .usage("show ports [--output table|json|yaml|csv]")
+                    .output_option()
+                    .example("show ports --output json", "shows the ports as JSON")
+                    .see_also("output")
+                    .action(|_, _| -> XcliResult { Ok(CmdExeCode::Ok) }),
+            )
+            .subcommand(
+                Command::new_with_alias("version", "v")
+                    .about("shows the firmware version")
+                    .usage("show version")
+                    .action(|_, _| -> XcliResult { Ok(CmdExeCode::Ok) }),
+            ),
+    );
+
+    print!("{}", app.generate_doc(format));
+}
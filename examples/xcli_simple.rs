@@ -1,6 +1,15 @@
-use log::{info, LevelFilter};
+use log::{debug, info, LevelFilter};
+use serde::Serialize;
 use xcli::*;
 
+#[derive(Serialize)]
+struct Port {
+    id: u32,
+    name: String,
+    up: bool,
+    description: String,
+}
+
 fn main() {
     env_logger::init();
 
@@ -8,7 +17,18 @@ fn main() {
 
     let mut app = App::new("xCLI")
         .version("v0.1")
-        .author("kingwel.xie@139.com");
+        .author("kingwel.xie@139.com")
+        // run with RUST_LOG=audit=info to see the audit log
+        .audit(Audit::to_log("audit"))
+        // the password of 'enable'
+        .on_enable(|_app, _level, password| password == "xcli")
+        .around(|_app, cmd, next| {
+            // run with RUST_LOG=debug to see how long commands take
+            let start = std::time::Instant::now();
+            let ret = next();
+            debug!("'{}' took {:?}", cmd.line(), start.elapsed());
+            ret
+        });
 
     app.add_subcommand(
         Command::new_with_alias("test1", "t1")
@@ -44,6 +64,156 @@ fn main() {
             }),
     );
 
+    app.add_subcommand(
+        Command::new("reboot")
+            .about("reboots the system, try 'enable' first, the password is 'xcli'")
+            .privilege(MAX_PRIVILEGE)
+            .action(|_app, _args| -> XcliResult {
+                xprintln!("rebooting...");
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("erase")
+            .about("erases all data, after confirmation")
+            .usage("erase [-y]")
+            .confirm("This will erase all data, continue?")
+            .action(|_app, args| -> XcliResult {
+                Args::new(args).at_most(0)?;
+                xprintln!("all data erased");
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("passwd")
+            .about("changes the password of a user, which is kept out of logs and history")
+            .usage("passwd <user> <password>")
+            .sensitive_arg(1)
+            .action(|_app, args| -> XcliResult {
+                let args = Args::new(args);
+                args.at_most(2)?;
+                let user: String = args.required(0, "user")?;
+                let _password: String = args.required(1, "password")?;
+                xprintln!("password of {} changed", user);
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("adduser")
+            .about("adds a user, asking for the details")
+            .action(|app, args| -> XcliResult {
+                Args::new(args).at_most(0)?;
+                let name = app.ask("Name: ")?;
+                let _password = app.ask_password("Password: ")?;
+                let shell = app.ask_with_default("Shell", "/bin/sh")?;
+                let role = app.choose("Role", &["admin", "operator", "guest"])?;
+                xprintln!("user {} added, shell {}, role {}", name, shell, role);
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(Command::new("device").about("the devices connected, see 'connect'"));
+    app.add_subcommand(
+        Command::new("connect")
+            .about("connects a device, which is added as a command under 'device' at runtime")
+            .usage("connect <name>")
+            .see_also("disconnect")
+            .action(|app, args| -> XcliResult {
+                let args = Args::new(args);
+                args.at_most(1)?;
+                let name = args.required::<String>(0, "name")?;
+                let device = Command::new(name).about("shows the device").action(|_app, _args| -> XcliResult {
+                    xprintln!("device is up");
+                    Ok(CmdExeCode::Ok)
+                });
+                app.add_command(&["device"], device)?;
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+    app.add_subcommand(
+        Command::new("disconnect")
+            .about("disconnects a device, which is removed from 'device'")
+            .usage("disconnect <name>")
+            .action(|app, args| -> XcliResult {
+                let args = Args::new(args);
+                args.at_most(1)?;
+                let name = args.required::<String>(0, "name")?;
+                app.remove_command(&["device", &name])?;
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("counter")
+            .about("counts to 10 slowly")
+            .deprecated("use 'count' instead")
+            .replaced_by("count")
+            .hidden(),
+    );
+    app.add_subcommand(
+        Command::new("turbo")
+            .about("counts to 10 quickly, run with XCLI_EXPERIMENTAL=1 to use it")
+            .experimental()
+            .action(|_app, _args| -> XcliResult {
+                xprintln!("{:?}", (1..=10).collect::<Vec<_>>());
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("cat")
+            .about("prints a file, I/O errors are reported with their causes")
+            .usage("cat <file>")
+            .action(|_app, args| -> XcliResult {
+                let args = Args::new(args);
+                args.at_most(1)?;
+                let file = args.required::<String>(0, "file")?;
+                xprint!("{}", std::fs::read_to_string(file)?);
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("count")
+            .about("counts to 10 slowly, try 'count &' to run it in background")
+            .usage("count [n]")
+            .arg("[n]", "the number to count to, 10 by default")
+            .example("count 3 &", "counts to 3 in background, see its output by 'fg'")
+            .see_also("jobs")
+            .action(|app, args| -> XcliResult {
+                let args = Args::new(args);
+                args.at_most(1)?;
+                let n = args.optional::<u32>(0)?.unwrap_or(10);
+                for i in 1..=n {
+                    if app.is_cancelled() {
+                        xprintln!("cancelled");
+                        break;
+                    }
+                    xprintln!("{}", i);
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+                Ok(CmdExeCode::Ok)
+            }),
+    );
+
+    app.add_subcommand(
+        Command::new("ports")
+            .about("shows the ports, try 'ports --output json'")
+            .usage("ports [--output table|json|yaml|csv]")
+            .output_option()
+            .action(|app, _args| -> XcliResult {
+                let ports = vec![
+                    Port { id: 1, name: "eth0".into(), up: true, description: "uplink to the core switch".into() },
+                    Port { id: 2, name: "eth1".into(), up: false, description: "".into() },
+                    Port { id: 10, name: "mgmt".into(), up: true, description: "management, \"out of band\"".into() },
+                ];
+                app.render(&ports)
+            }),
+    );
+
     app.add_subcommand_with_userdata(
         Command::new_with_alias("userdata", "ud")
             .about("controls testing features")
@@ -58,5 +228,8 @@ fn main() {
         Box::new(100usize)
     );
 
-    app.run();
+    app.set_experimental(std::env::var("XCLI_EXPERIMENTAL").is_ok());
+    // run a single command if given, e.g. "xcli_simple count 3", otherwise the CLI loop
+    let status = app.run_from_args(std::env::args());
+    std::process::exit(status.code());
 }
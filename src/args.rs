@@ -0,0 +1,185 @@
+//! Typed access to the arguments of a command.
+
+use std::cell::Cell;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::XcliError;
+
+/// Get the short name of type T, e.g. "String" instead of "alloc::string::String".
+fn type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    // generic types are kept as they are, e.g. "core::option::Option<u32>"
+    if name.contains('<') {
+        return name;
+    }
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Parse value of the argument name into T.
+pub(crate) fn parse_value<T>(value: &str, name: &str) -> Result<T, XcliError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse::<T>().map_err(|err| XcliError::InvalidArgument {
+        name: name.to_string(),
+        value: value.to_string(),
+        expected: type_name::<T>().to_string(),
+        reason: err.to_string(),
+    })
+}
+
+/// The arguments of a command, which can be extracted as typed values.
+///
+/// Values are parsed by `FromStr`, and errors name the argument and the
+/// type expected.
+///
+/// ```
+/// # use xcli::*;
+/// fn cli_port(_app: &App, args: &[&str]) -> XcliResult {
+///     let args = Args::new(args);
+///     let port = args.required::<u32>(0, "port")?;
+///     let name = args.optional::<String>(1)?.unwrap_or_default();
+///     xprintln!("{} {} {:?}", port, name, args.rest());
+///     Ok(CmdExeCode::Ok)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Args<'b> {
+    args: &'b [&'b str],
+    /// The index after the last argument extracted, where `rest` starts.
+    next: Cell<usize>,
+}
+
+impl<'b> Args<'b> {
+    /// Wrap the arguments given to an action.
+    pub fn new(args: &'b [&'b str]) -> Self {
+        Args {
+            args,
+            next: Cell::new(0),
+        }
+    }
+
+    /// Get the number of arguments.
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Whether there is no argument.
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Get the argument at idx as it is.
+    pub fn get(&self, idx: usize) -> Option<&'b str> {
+        self.args.get(idx).copied()
+    }
+
+    /// Get all the arguments.
+    pub fn as_slice(&self) -> &'b [&'b str] {
+        self.args
+    }
+
+    /// Mark the argument at idx as extracted.
+    fn take(&self, idx: usize) -> Option<&'b str> {
+        self.next.set(self.next.get().max(idx + 1));
+        self.get(idx)
+    }
+
+    /// Get the argument at idx as T, which is required, with name in the errors.
+    pub fn required<T>(&self, idx: usize, name: &str) -> Result<T, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .take(idx)
+            .ok_or_else(|| XcliError::RequiredArgument(name.to_string()))?;
+        parse_value(value, name)
+    }
+
+    /// Get the argument at idx as T, if it is given.
+    pub fn optional<T>(&self, idx: usize) -> Result<Option<T>, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let name = format!("argument {}", idx + 1);
+        self.take(idx).map(|value| parse_value(value, &name)).transpose()
+    }
+
+    /// Get the arguments after the ones extracted by `required` or `optional`.
+    pub fn rest(&self) -> &'b [&'b str] {
+        &self.args[self.next.get().min(self.args.len())..]
+    }
+
+    /// Check that there are count arguments at most.
+    pub fn at_most(&self, count: usize) -> Result<(), XcliError> {
+        if self.args.len() > count {
+            return Err(XcliError::MismatchArgument(count, self.args.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Check the number of arguments, and convert them by `TryFrom<&str>`.
+///
+/// `check_param!(count, required, args, (Type => has_from, ...))` returns a
+/// tuple of `Option`s, one for each type, `None` if the argument is not given.
+#[deprecated(note = "use `Args` instead, which names the argument in the errors")]
+#[macro_export]
+macro_rules! check_param {
+    ($param_count:expr, $required:expr, $args:ident, ($($change_type:ty=>$has_from:expr), *)) => {{
+        let args = $crate::Args::new(&$args[..]);
+        args.at_most($param_count)?;
+        if args.len() < $required {
+            return Err($crate::XcliError::MismatchArgument($required, args.len()));
+        }
+
+        let mut _idx = 0;
+        (
+            $({
+                _idx += 1;
+                match args.get(_idx - 1) {
+                    Some(arg) => Some(
+                        <$change_type as ::std::convert::TryFrom<&str>>::try_from(arg)
+                            .map_err(|e| $crate::XcliError::BadArgument(e.to_string()))?,
+                    ),
+                    None => None,
+                }
+            },)*
+        )
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_values() {
+        let args = Args::new(&["3", "eth0", "a", "b"]);
+        assert_eq!(args.required::<u32>(0, "port").unwrap(), 3);
+        assert_eq!(args.optional::<String>(1).unwrap().as_deref(), Some("eth0"));
+        assert_eq!(args.rest(), ["a", "b"]);
+        assert_eq!(args.optional::<u32>(9).unwrap(), None);
+        assert!(matches!(args.required::<u32>(9, "id"), Err(XcliError::RequiredArgument(_))));
+        assert!(matches!(args.required::<u32>(1, "id"), Err(XcliError::InvalidArgument { .. })));
+        assert!(args.at_most(3).is_err());
+    }
+
+    #[allow(deprecated)]
+    fn check_param_action(args: &[&str]) -> Result<(Option<String>, Option<String>), XcliError> {
+        let (name, value) = check_param!(2, 1, args, (String => true, String => true));
+        Ok((name, value))
+    }
+
+    #[test]
+    fn check_param_shim() {
+        assert_eq!(check_param_action(&["a"]).unwrap(), (Some("a".to_string()), None));
+        assert_eq!(check_param_action(&["a", "b"]).unwrap().1.as_deref(), Some("b"));
+        assert!(matches!(check_param_action(&[]), Err(XcliError::MismatchArgument(1, 0))));
+        assert!(matches!(check_param_action(&["a", "b", "c"]), Err(XcliError::MismatchArgument(2, 3))));
+    }
+}
@@ -0,0 +1,136 @@
+//! Audit log of the commands executed.
+//!
+//! Each command is recorded as a line of JSON, with the timestamp, session id,
+//! user, command path, arguments and result, e.g.
+//!
+//! ```text
+//! {"timestamp":"2026-01-02T03:04:05Z","session":"18f3a2c41b-4711","user":"admin","path":"user add","args":["bob","--password","***"],"result":"Ok"}
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+
+/// The text replacing sensitive arguments.
+pub(crate) const REDACTED: &str = "***";
+
+/// Where the records go.
+enum Sink {
+    File(Mutex<File>),
+    Log(String),
+}
+
+/// The audit log, set by `App::audit`.
+///
+/// Arguments marked by `Command::sensitive_arg` or `Command::sensitive_flag`
+/// are redacted.
+///
+/// ```no_run
+/// # use xcli::*;
+/// let app = App::new("xCLI").audit(Audit::to_file("audit.log").unwrap().user("admin"));
+/// ```
+pub struct Audit {
+    sink: Sink,
+    session: String,
+    user: String,
+}
+
+impl Audit {
+    fn new(sink: Sink) -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+        Audit {
+            sink,
+            session: session_id(),
+            user,
+        }
+    }
+
+    /// Append the records to the file, which is created if not found.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Audit::new(Sink::File(Mutex::new(file))))
+    }
+
+    /// Write the records to the `log` target, at info level.
+    pub fn to_log<S: Into<String>>(target: S) -> Self {
+        Audit::new(Sink::Log(target.into()))
+    }
+
+    /// Set the user, which is `$USER` by default.
+    pub fn user<S: Into<String>>(mut self, user: S) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    /// Set the session id, which is generated by the start time and process id by default.
+    pub fn session<S: Into<String>>(mut self, session: S) -> Self {
+        self.session = session.into();
+        self
+    }
+
+    /// Get the session id.
+    pub fn get_session(&self) -> &str {
+        &self.session
+    }
+
+    /// Write a record of the command at path, with the arguments redacted already.
+    pub(crate) fn record(&self, path: &[&str], args: &[String], result: &str) {
+        let record = serde_json::json!({
+            "timestamp": timestamp(SystemTime::now()),
+            "session": self.session,
+            "user": self.user,
+            "path": path.join(" "),
+            "args": args,
+            "result": result,
+        });
+
+        match &self.sink {
+            Sink::File(file) => {
+                let mut file = file.lock().unwrap();
+                if let Err(err) = writeln!(file, "{}", record).and_then(|_| file.flush()) {
+                    error!("failed to write audit log: {}", err);
+                }
+            }
+            Sink::Log(target) => info!(target: target, "{}", record),
+        }
+    }
+}
+
+/// Generate a session id by the time and the process id.
+fn session_id() -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    format!("{:x}-{}", millis, std::process::id())
+}
+
+/// Format time as RFC 3339 in UTC, e.g. "2026-01-02T03:04:05Z".
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // the civil date from days since 1970-01-01, by Howard Hinnant's algorithm
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
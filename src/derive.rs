@@ -0,0 +1,203 @@
+//! Commands defined by types, with `#[derive(XcliCommand)]`.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::args::parse_value;
+use crate::{App, Command, XcliError, XcliResult};
+
+/// Types which define commands, by `#[derive(XcliCommand)]` with the
+/// `derive` feature.
+///
+/// Each variant of an enum, or a struct, is a command, named after it in
+/// kebab-case. Doc comments are the descriptions, and fields are the
+/// arguments:
+///
+/// * `#[arg]`, or no attribute, a positional argument. It is optional if the
+///   type is `Option<T>`, and takes the rest of the arguments if it is `Vec<T>`.
+/// * `#[flag]`, a `bool` switched on by `--name`.
+/// * `#[opt]`, an `Option<T>` given by `--name <value>` or `--name=<value>`.
+///
+/// Values are parsed by `FromStr`, and executed by `Execute`. A tuple variant
+/// with a single field, whose type derives `XcliCommand` as well, is a command
+/// with subcommands, whose values are wrapped in the variant, and executed by
+/// the `Execute` of the enum, which usually passes them to the field type.
+/// `#[flag]` on a field other than `bool` is a compile error.
+/// `#[xcli(name = "...", alias = "...", usage = "...")]` overrides the defaults.
+///
+/// ```ignore
+/// use xcli::*;
+///
+/// #[derive(XcliCommand)]
+/// enum Port {
+///     /// shows a port
+///     Show {
+///         /// the port id
+///         #[arg]
+///         id: u32,
+///         /// shows the counters as well
+///         #[flag]
+///         verbose: bool,
+///     },
+/// }
+///
+/// impl Execute for Port {
+///     fn execute(self, _app: &App) -> XcliResult {
+///         match self {
+///             Port::Show { id, verbose } => xprintln!("port {}, verbose: {}", id, verbose),
+///         }
+///         Ok(CmdExeCode::Ok)
+///     }
+/// }
+///
+/// let mut app = App::new("xCLI");
+/// app.add_commands::<Port>();
+/// ```
+pub trait XcliCommand {
+    /// Get the commands defined by this type.
+    fn commands() -> Vec<Command<'static>>
+    where
+        Self: Execute + Sized,
+    {
+        Self::commands_in::<Root>()
+    }
+
+    /// Get the commands defined by this type, whose values are executed by
+    /// the dispatcher, e.g. wrapped in a variant of the parent type.
+    #[doc(hidden)]
+    fn commands_in<D: Dispatch<Self>>() -> Vec<Command<'static>>
+    where
+        Self: Sized;
+}
+
+/// The action of commands defined by `#[derive(XcliCommand)]`, which is
+/// called with the arguments parsed into the type.
+pub trait Execute {
+    /// Execute the command.
+    fn execute(self, app: &App) -> XcliResult;
+}
+
+/// How the values of the commands defined by `#[derive(XcliCommand)]` are
+/// executed, for the code generated.
+#[doc(hidden)]
+pub trait Dispatch<T> {
+    fn dispatch(value: T, app: &App) -> XcliResult;
+}
+
+/// The dispatcher executing the values by their own `Execute`.
+#[doc(hidden)]
+pub struct Root;
+
+impl<T: Execute> Dispatch<T> for Root {
+    fn dispatch(value: T, app: &App) -> XcliResult {
+        value.execute(app)
+    }
+}
+
+/// Arguments split into positional ones, flags and options, for the code
+/// generated by `#[derive(XcliCommand)]`.
+#[doc(hidden)]
+pub struct Parsed<'b> {
+    positional: std::vec::IntoIter<&'b str>,
+    flags: Vec<&'b str>,
+    options: Vec<(&'b str, &'b str)>,
+}
+
+impl<'b> Parsed<'b> {
+    /// Split args with the names of flags and options known, e.g. "--verbose".
+    ///
+    /// Arguments after "--" are all positional.
+    pub fn new(args: &[&'b str], flags: &[&str], options: &[&str]) -> Result<Self, XcliError> {
+        let mut positional = vec![];
+        let mut found_flags = vec![];
+        let mut found_options = vec![];
+
+        let mut iter = args.iter();
+        while let Some(&arg) = iter.next() {
+            if arg == "--" {
+                positional.extend(iter);
+                break;
+            }
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+
+            if flags.contains(&arg) {
+                found_flags.push(arg);
+            } else if options.contains(&arg) {
+                let value = iter.next().ok_or_else(|| XcliError::RequiredArgument(format!("{} <value>", arg)))?;
+                found_options.push((arg, *value));
+            } else {
+                match arg.split_once('=') {
+                    Some((name, value)) if options.contains(&name) => found_options.push((name, value)),
+                    _ => return Err(XcliError::BadArgument(format!("{}, unknown option", arg))),
+                }
+            }
+        }
+
+        Ok(Parsed {
+            positional: positional.into_iter(),
+            flags: found_flags,
+            options: found_options,
+        })
+    }
+
+    /// Take the next positional argument, which is required.
+    pub fn required<T>(&mut self, name: &str) -> Result<T, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .positional
+            .next()
+            .ok_or_else(|| XcliError::RequiredArgument(name.to_string()))?;
+        parse_value(value, name)
+    }
+
+    /// Take the next positional argument, if any.
+    pub fn optional<T>(&mut self, name: &str) -> Result<Option<T>, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.positional.next().map(|value| parse_value(value, name)).transpose()
+    }
+
+    /// Take the rest positional arguments.
+    pub fn rest<T>(&mut self, name: &str) -> Result<Vec<T>, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.positional.by_ref().map(|value| parse_value(value, name)).collect()
+    }
+
+    /// Whether the flag is given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+
+    /// Get the value of the option, the last one if it is given more than once.
+    pub fn option<T>(&self, name: &str) -> Result<Option<T>, XcliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| parse_value(value, name))
+            .transpose()
+    }
+
+    /// Check that all positional arguments are taken.
+    pub fn finish(mut self) -> Result<(), XcliError> {
+        match self.positional.next() {
+            Some(arg) => Err(XcliError::BadArgument(format!("{}, unexpected argument", arg))),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Reference documents generated from the command tree, as a man page,
+//! Markdown or HTML.
+//!
+//! See [`App::generate_doc`], and the `xcli_doc` example.
+//!
+//! [`App::generate_doc`]: crate::App::generate_doc
+
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::{App, Command, XcliError};
+
+/// The format of generated documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    /// A roff man page, in section 1.
+    Man,
+    /// Markdown.
+    Markdown,
+    /// A standalone HTML page.
+    Html,
+}
+
+impl FromStr for DocFormat {
+    type Err = XcliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "man" | "roff" => Ok(DocFormat::Man),
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            "html" => Ok(DocFormat::Html),
+            _ => Err(XcliError::BadArgument(format!(
+                "{}, one of man, markdown and html expected",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DocFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DocFormat::Man => "man",
+            DocFormat::Markdown => "markdown",
+            DocFormat::Html => "html",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A command to document, with its full name, e.g. "terminal length", and
+/// its depth in the tree.
+struct Entry<'c, 'a> {
+    name: String,
+    depth: usize,
+    cmd: &'c Command<'a>,
+}
+
+/// Collect all commands of the tree, in the order of `Command::for_each`.
+fn entries<'c, 'a>(tree: &'c Command<'a>) -> Vec<Entry<'c, 'a>> {
+    let mut entries = vec![];
+    tree.for_each("", &mut |cmd, path| {
+        // path is like "/terminal/length", and "" for the root
+        if !path.is_empty() {
+            entries.push(Entry {
+                name: path[1..].replace('/', " "),
+                depth: path.matches('/').count(),
+                cmd,
+            });
+        }
+    });
+    entries
+}
+
+/// Make an anchor of the command name, e.g. "terminal-length".
+fn anchor(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Escape text for roff.
+fn roff(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    // a line beginning with '.' or '\'' would be a request
+    s.lines()
+        .map(|line| {
+            if line.starts_with(['.', '\'']) {
+                format!("\\&{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escape text for HTML.
+fn html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escape text for Markdown, out of code spans.
+fn markdown(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '<' | '>' | '[' | ']' | '|') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn generate_man(app: &App, entries: &[Entry]) -> String {
+    let mut s = String::new();
+    let name = app.get_name();
+    let _ = writeln!(
+        s,
+        ".TH \"{}\" 1 \"\" \"{}\" \"{} Manual\"",
+        roff(&name.to_uppercase()),
+        roff(app.get_version()),
+        roff(name)
+    );
+    let _ = writeln!(s, ".SH NAME\n{} \\- {}", roff(name), roff(app.tree().about.unwrap_or("")));
+    if !app.get_author().is_empty() {
+        let _ = writeln!(s, ".SH AUTHOR\n{}", roff(app.get_author()));
+    }
+    let _ = writeln!(s, ".SH COMMANDS");
+
+    for e in entries {
+        let cmd = e.cmd;
+        let _ = writeln!(s, ".SS \"{}\"", roff(&e.name));
+        let _ = writeln!(s, ".B {}", roff(cmd.usage.unwrap_or(&e.name)));
+        if let Some(alias) = cmd.alias.as_ref() {
+            let _ = writeln!(s, ".br\nAlias: {}", roff(alias));
+        }
+        if let Some(about) = cmd.about {
+            let _ = writeln!(s, ".PP\n{}", roff(about));
+        }
+        if let Some(long_about) = cmd.long_about {
+            for paragraph in long_about.split('\n') {
+                let _ = writeln!(s, ".PP\n{}", roff(paragraph));
+            }
+        }
+        for (title, items) in [("Arguments", &cmd.args), ("Flags", &cmd.flags)] {
+            if !items.is_empty() {
+                let _ = writeln!(s, ".PP\n{}:", title);
+                for (name, help) in items {
+                    let _ = writeln!(s, ".TP\n.B {}\n{}", roff(name), roff(help));
+                }
+            }
+        }
+        if !cmd.subcommands.is_empty() {
+            let subs: Vec<_> = cmd
+                .subcommands
+                .iter()
+                .map(|c| roff(&format!("{} {}", e.name, c.name)))
+                .collect();
+            let _ = writeln!(s, ".PP\nSubcommands: {}", subs.join(", "));
+        }
+        if !cmd.examples.is_empty() {
+            let _ = writeln!(s, ".PP\nExamples:");
+            for (example, help) in &cmd.examples {
+                let _ = writeln!(s, ".PP\n{}\n.RS\n.B {}\n.RE", roff(help), roff(example));
+            }
+        }
+        if !cmd.see_also.is_empty() {
+            let _ = writeln!(s, ".PP\nSee also: {}", roff(&cmd.see_also.join(", ")));
+        }
+    }
+    s
+}
+
+fn generate_markdown(app: &App, entries: &[Entry]) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "# {}\n", markdown(app.get_name()));
+    if let Some(about) = app.tree().about {
+        let _ = writeln!(s, "{}\n", markdown(about));
+    }
+    if !app.get_version().is_empty() {
+        let _ = writeln!(s, "Version: {}\n", markdown(app.get_version()));
+    }
+    let _ = writeln!(s, "## Commands\n");
+
+    for e in entries {
+        let cmd = e.cmd;
+        let level = (e.depth + 2).min(6);
+        let _ = writeln!(s, "{} `{}`\n", "#".repeat(level), e.name);
+        if let Some(about) = cmd.about {
+            let _ = writeln!(s, "{}\n", markdown(about));
+        }
+        let _ = writeln!(s, "Usage: `{}`\n", cmd.usage.unwrap_or(&e.name));
+        if let Some(alias) = cmd.alias.as_ref() {
+            let _ = writeln!(s, "Alias: `{}`\n", alias);
+        }
+        if let Some(long_about) = cmd.long_about {
+            for paragraph in long_about.split('\n') {
+                let _ = writeln!(s, "{}\n", markdown(paragraph));
+            }
+        }
+        for (title, items) in [("Arguments", &cmd.args), ("Flags", &cmd.flags)] {
+            if !items.is_empty() {
+                let _ = writeln!(s, "{}:\n", title);
+                for (name, help) in items {
+                    let _ = writeln!(s, "- `{}`: {}", name, markdown(help));
+                }
+                s.push('\n');
+            }
+        }
+        if !cmd.subcommands.is_empty() {
+            let subs: Vec<_> = cmd
+                .subcommands
+                .iter()
+                .map(|c| {
+                    let name = format!("{} {}", e.name, c.name);
+                    format!("[`{}`](#{})", name, anchor(&name))
+                })
+                .collect();
+            let _ = writeln!(s, "Subcommands: {}\n", subs.join(", "));
+        }
+        if !cmd.examples.is_empty() {
+            let _ = writeln!(s, "Examples:\n\n```text");
+            for (example, help) in &cmd.examples {
+                let _ = writeln!(s, "# {}\n{}", help, example);
+            }
+            let _ = writeln!(s, "```\n");
+        }
+        if !cmd.see_also.is_empty() {
+            let links: Vec<_> = cmd
+                .see_also
+                .iter()
+                .map(|c| format!("[`{}`](#{})", c, anchor(c)))
+                .collect();
+            let _ = writeln!(s, "See also: {}\n", links.join(", "));
+        }
+    }
+    s
+}
+
+fn generate_html(app: &App, entries: &[Entry]) -> String {
+    let mut s = String::new();
+    let name = html(app.get_name());
+    let _ = writeln!(
+        s,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>",
+        name
+    );
+    let _ = writeln!(s, "<h1>{}</h1>", name);
+    if let Some(about) = app.tree().about {
+        let _ = writeln!(s, "<p>{}</p>", html(about));
+    }
+    if !app.get_version().is_empty() {
+        let _ = writeln!(s, "<p>Version: {}</p>", html(app.get_version()));
+    }
+    let _ = writeln!(s, "<h2>Commands</h2>");
+
+    for e in entries {
+        let cmd = e.cmd;
+        let level = (e.depth + 2).min(6);
+        let _ = writeln!(
+            s,
+            "<h{0} id=\"{1}\"><code>{2}</code></h{0}>",
+            level,
+            anchor(&e.name),
+            html(&e.name)
+        );
+        if let Some(about) = cmd.about {
+            let _ = writeln!(s, "<p>{}</p>", html(about));
+        }
+        let _ = writeln!(s, "<p>Usage: <code>{}</code></p>", html(cmd.usage.unwrap_or(&e.name)));
+        if let Some(alias) = cmd.alias.as_ref() {
+            let _ = writeln!(s, "<p>Alias: <code>{}</code></p>", html(alias));
+        }
+        if let Some(long_about) = cmd.long_about {
+            for paragraph in long_about.split('\n') {
+                let _ = writeln!(s, "<p>{}</p>", html(paragraph));
+            }
+        }
+        for (title, items) in [("Arguments", &cmd.args), ("Flags", &cmd.flags)] {
+            if !items.is_empty() {
+                let _ = writeln!(s, "<p>{}:</p>\n<dl>", title);
+                for (name, help) in items {
+                    let _ = writeln!(s, "<dt><code>{}</code></dt><dd>{}</dd>", html(name), html(help));
+                }
+                let _ = writeln!(s, "</dl>");
+            }
+        }
+        if !cmd.subcommands.is_empty() {
+            let subs: Vec<_> = cmd
+                .subcommands
+                .iter()
+                .map(|c| {
+                    let name = format!("{} {}", e.name, c.name);
+                    format!("<a href=\"#{}\"><code>{}</code></a>", anchor(&name), html(&name))
+                })
+                .collect();
+            let _ = writeln!(s, "<p>Subcommands: {}</p>", subs.join(", "));
+        }
+        if !cmd.examples.is_empty() {
+            let _ = writeln!(s, "<p>Examples:</p>\n<pre>");
+            for (example, help) in &cmd.examples {
+                let _ = writeln!(s, "# {}\n{}", html(help), html(example));
+            }
+            let _ = writeln!(s, "</pre>");
+        }
+        if !cmd.see_also.is_empty() {
+            let links: Vec<_> = cmd
+                .see_also
+                .iter()
+                .map(|c| format!("<a href=\"#{}\"><code>{}</code></a>", anchor(c), html(c)))
+                .collect();
+            let _ = writeln!(s, "<p>See also: {}</p>", links.join(", "));
+        }
+    }
+    let _ = writeln!(s, "</body>\n</html>");
+    s
+}
+
+/// Generate the reference document of all commands of app, except the hidden,
+/// disabled, or experimental ones unless enabled.
+pub(crate) fn generate(app: &App, format: DocFormat) -> String {
+    let tree = app.tree().visible(u8::MAX, app.is_experimental());
+    let entries = entries(&tree);
+    match format {
+        DocFormat::Man => generate_man(app, &entries),
+        DocFormat::Markdown => generate_markdown(app, &entries),
+        DocFormat::Html => generate_html(app, &entries),
+    }
+}
@@ -0,0 +1,503 @@
+//! Structured output of commands.
+//!
+//! Instead of formatting tables by hand, an action can render any
+//! `serde::Serialize` value, or a [`Table`], by [`App::render`]. The output
+//! format is the session-wide one managed by the `output` builtin, unless the
+//! command accepts an `--output <format>` option by `Command::output_option`,
+//! and it is given, which is removed from the arguments before calling the action.
+//!
+//! [`App::render`]: crate::App::render
+
+use std::cell::Cell;
+use std::fmt;
+use std::io::{stdout, IsTerminal};
+use std::str::FromStr;
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::Value;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::XcliError;
+
+/// The option to specify the output format of a command.
+const OUTPUT_OPTION: &str = "--output";
+
+/// The text of a truncated cell ends with it.
+const ELLIPSIS: char = '…';
+
+/// Columns are never truncated narrower than it.
+const MIN_COLUMN_WIDTH: usize = 6;
+
+thread_local! {
+    static FORMAT: Cell<Option<OutputFormat>> = const { Cell::new(None) };
+}
+
+/// The output format of structured data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Aligned columns, truncated to the width of terminal.
+    #[default]
+    Table,
+    /// Pretty printed JSON.
+    Json,
+    /// YAML.
+    Yaml,
+    /// Comma separated values, with a header line.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = XcliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(XcliError::BadArgument(format!("{}, one of table, json, yaml and csv expected", s))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+            OutputFormat::Csv => "csv",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A table of rows, with column headers.
+///
+/// It is serialized as a sequence of maps, from the headers to the cells.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Create a table with the column headers
+    pub fn new<I, S>(headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        Table {
+            headers: headers.into_iter().map(|h| h.to_string()).collect(),
+            rows: vec![],
+        }
+    }
+
+    /// Add a row to this table
+    pub fn row<I, S>(mut self, row: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.add_row(row);
+        self
+    }
+
+    /// Add a row to this table
+    pub fn add_row<I, S>(&mut self, row: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.rows.push(row.into_iter().map(|c| c.to_string()).collect());
+    }
+
+    /// Get the column headers of this table
+    pub fn get_headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// Get the rows of this table
+    pub fn get_rows(&self) -> &[Vec<String>] {
+        &self.rows
+    }
+}
+
+struct TableRow<'t>(&'t [String], &'t [String]);
+
+impl Serialize for TableRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (i, header) in self.0.iter().enumerate() {
+            map.serialize_entry(header, self.1.get(i).map_or("", String::as_str))?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in &self.rows {
+            seq.serialize_element(&TableRow(&self.headers, row))?;
+        }
+        seq.end()
+    }
+}
+
+/// Remove the `--output <format>` option from args, if any, before `--`.
+///
+/// The option is left in args if the format is not a valid one, in case it
+/// is some option of the command.
+pub(crate) fn take_output_option<'b>(args: &[&'b str]) -> (Vec<&'b str>, Option<OutputFormat>) {
+    let mut rest = vec![];
+    let mut format = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "--" {
+            rest.extend_from_slice(&args[i..]);
+            break;
+        }
+        let (value, len) = if let Some(value) = args[i].strip_prefix("--output=") {
+            (Some(value), 1)
+        } else if args[i] == OUTPUT_OPTION {
+            (args.get(i + 1).copied(), 2)
+        } else {
+            (None, 1)
+        };
+
+        match value.map(str::parse::<OutputFormat>) {
+            Some(Ok(f)) => {
+                format = Some(f);
+                i += len;
+            }
+            _ => {
+                rest.push(args[i]);
+                i += 1;
+            }
+        }
+    }
+    (rest, format)
+}
+
+/// Call f with the output format of the calling thread overridden, if format
+/// is given, otherwise the one of the calling command is kept, e.g. for `watch`.
+pub(crate) fn with_format<F, R>(format: Option<OutputFormat>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let saved = FORMAT.with(Cell::get);
+    if format.is_some() {
+        FORMAT.with(|c| c.set(format));
+    }
+    let ret = f();
+    FORMAT.with(|c| c.set(saved));
+    ret
+}
+
+/// Get the output format of the command running on the calling thread, if specified.
+pub(crate) fn current_format() -> Option<OutputFormat> {
+    FORMAT.with(Cell::get)
+}
+
+/// Convert a value to a string in a cell.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// Convert a value to the column headers and rows of a table.
+fn to_rows(value: &Value) -> (Vec<String>, Vec<Vec<String>>) {
+    match value {
+        Value::Array(items) if items.iter().all(Value::is_object) => {
+            let mut headers: Vec<String> = vec![];
+            for item in items.iter().filter_map(Value::as_object) {
+                for key in item.keys() {
+                    if !headers.contains(key) {
+                        headers.push(key.clone());
+                    }
+                }
+            }
+            let rows = items
+                .iter()
+                .map(|item| headers.iter().map(|h| item.get(h).map(cell).unwrap_or_default()).collect())
+                .collect();
+            (headers, rows)
+        }
+        Value::Array(items) => (vec!["VALUE".to_string()], items.iter().map(|v| vec![cell(v)]).collect()),
+        Value::Object(map) => (
+            vec!["KEY".to_string(), "VALUE".to_string()],
+            map.iter().map(|(k, v)| vec![k.clone(), cell(v)]).collect(),
+        ),
+        v => (vec!["VALUE".to_string()], vec![vec![cell(v)]]),
+    }
+}
+
+/// Truncate s to the display width, with an ellipsis.
+///
+/// Wide characters, e.g. CJK, take two columns, so the text may be a column
+/// narrower than width.
+fn truncate(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_string();
+    }
+    let mut t = String::new();
+    let mut w = 0;
+    for c in s.chars() {
+        w += c.width().unwrap_or(0);
+        if w + ELLIPSIS.width().unwrap_or(1) > width {
+            break;
+        }
+        t.push(c);
+    }
+    t.push(ELLIPSIS);
+    t
+}
+
+/// Render rows as aligned columns, which fit in max_width if any.
+fn render_table(headers: &[String], rows: &[Vec<String>], max_width: Option<usize>) -> String {
+    const GAP: usize = 2;
+
+    // multi-line cells would break the table
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(|c| c.replace(['\r', '\n'], " ")).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut widths = headers.iter().map(|h| h.width()).collect::<Vec<_>>();
+    for row in &rows {
+        for (i, c) in row.iter().enumerate().take(widths.len()) {
+            widths[i] = widths[i].max(c.width());
+        }
+    }
+
+    // shrink the widest column, until the table fits
+    if let Some(max_width) = max_width {
+        let gaps = GAP * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + gaps > max_width {
+            let (i, w) = widths.iter().copied().enumerate().max_by_key(|(_, w)| *w).unwrap_or((0, 0));
+            if w <= MIN_COLUMN_WIDTH {
+                break;
+            }
+            widths[i] = w - 1;
+        }
+    }
+
+    // numbers are right aligned
+    let numeric = (0..widths.len())
+        .map(|i| !rows.is_empty() && rows.iter().all(|r| r.get(i).is_some_and(|c| c.parse::<f64>().is_ok())))
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    let mut line = |cells: &mut dyn Iterator<Item = (usize, String)>| {
+        let mut s = String::new();
+        for (i, c) in cells {
+            let c = truncate(&c, widths[i]);
+            let pad = " ".repeat(widths[i].saturating_sub(c.width()));
+            if i > 0 {
+                s.push_str(&" ".repeat(GAP));
+            }
+            if numeric[i] {
+                s.push_str(&pad);
+                s.push_str(&c);
+            } else {
+                s.push_str(&c);
+                s.push_str(&pad);
+            }
+        }
+        out.push_str(s.trim_end());
+        out.push('\n');
+    };
+
+    line(&mut headers.iter().cloned().enumerate());
+    line(&mut widths.iter().map(|w| "-".repeat(*w)).enumerate());
+    for row in rows {
+        let mut cells = (0..widths.len()).map(|i| (i, row.get(i).cloned().unwrap_or_default()));
+        line(&mut cells);
+    }
+    out
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Render rows as comma separated values.
+fn render_csv(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in std::iter::once(headers).chain(rows.iter().map(Vec::as_slice)) {
+        let fields = row.iter().map(|c| csv_field(c)).collect::<Vec<_>>();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Format a string as a YAML scalar, quoted if necessary.
+fn yaml_string(s: &str) -> String {
+    let plain = !s.is_empty()
+        && !s.starts_with(|c: char| c.is_whitespace() || "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !s.ends_with(char::is_whitespace)
+        && !s.contains(": ")
+        && !s.contains(" #")
+        && !s.contains(|c: char| c.is_control())
+        && !matches!(s.to_lowercase().as_str(), "null" | "~" | "true" | "false" | "yes" | "no" | "on" | "off")
+        && s.parse::<f64>().is_err();
+
+    if plain {
+        s.to_string()
+    } else {
+        // a JSON string is a valid YAML double quoted scalar
+        Value::String(s.to_string()).to_string()
+    }
+}
+
+/// Render value as YAML, at the indent level.
+fn render_yaml(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                out.push_str(&pad);
+                out.push('-');
+                match item {
+                    Value::Array(a) if !a.is_empty() => {
+                        out.push('\n');
+                        render_yaml(item, indent + 1, out);
+                    }
+                    Value::Object(m) if !m.is_empty() => {
+                        // the first entry follows '-' in the same line
+                        let mut nested = String::new();
+                        render_yaml(item, indent + 1, &mut nested);
+                        out.push(' ');
+                        out.push_str(nested.trim_start());
+                    }
+                    v => {
+                        out.push(' ');
+                        render_yaml(v, 0, out);
+                    }
+                }
+            }
+        }
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                out.push_str(&pad);
+                out.push_str(&yaml_string(k));
+                out.push(':');
+                match v {
+                    Value::Array(a) if !a.is_empty() => {
+                        out.push('\n');
+                        render_yaml(v, indent, out);
+                    }
+                    Value::Object(m) if !m.is_empty() => {
+                        out.push('\n');
+                        render_yaml(v, indent + 1, out);
+                    }
+                    v => {
+                        out.push(' ');
+                        render_yaml(v, 0, out);
+                    }
+                }
+            }
+        }
+        Value::Array(_) => out.push_str("[]\n"),
+        Value::Object(_) => out.push_str("{}\n"),
+        Value::Null => out.push_str("null\n"),
+        Value::String(s) => {
+            out.push_str(&yaml_string(s));
+            out.push('\n');
+        }
+        v => {
+            out.push_str(&v.to_string());
+            out.push('\n');
+        }
+    }
+}
+
+/// Render value in the format.
+pub(crate) fn render<T: Serialize + ?Sized>(value: &T, format: OutputFormat) -> Result<String, XcliError> {
+    let value = serde_json::to_value(value).map_err(|err| XcliError::Other(err.to_string()))?;
+
+    let out = match format {
+        OutputFormat::Json => {
+            let mut s = serde_json::to_string_pretty(&value).map_err(|err| XcliError::Other(err.to_string()))?;
+            s.push('\n');
+            s
+        }
+        OutputFormat::Yaml => {
+            let mut s = String::new();
+            render_yaml(&value, 0, &mut s);
+            s
+        }
+        OutputFormat::Csv => {
+            let (headers, rows) = to_rows(&value);
+            render_csv(&headers, &rows)
+        }
+        OutputFormat::Table => {
+            let (headers, rows) = to_rows(&value);
+            // truncate the table only when it is shown on a terminal
+            let width = if stdout().is_terminal() {
+                crossterm::terminal::size().ok().map(|(w, _)| w as usize)
+            } else {
+                None
+            };
+            render_table(&headers, &rows, width)
+        }
+    };
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_option() {
+        let (rest, format) = take_output_option(&["a", "--output", "json", "b"]);
+        assert_eq!((rest, format), (vec!["a", "b"], Some(OutputFormat::Json)));
+        let (rest, format) = take_output_option(&["--output=csv"]);
+        assert_eq!((rest, format), (vec![], Some(OutputFormat::Csv)));
+        let (rest, format) = take_output_option(&["--output", "file.txt"]);
+        assert_eq!((rest, format), (vec!["--output", "file.txt"], None));
+        let (rest, format) = take_output_option(&["--", "--output", "json"]);
+        assert_eq!((rest, format), (vec!["--", "--output", "json"], None));
+    }
+
+    #[test]
+    fn nested_format() {
+        with_format(Some(OutputFormat::Json), || {
+            assert_eq!(with_format(None, current_format), Some(OutputFormat::Json));
+            assert_eq!(with_format(Some(OutputFormat::Csv), current_format), Some(OutputFormat::Csv));
+        });
+        assert_eq!(current_format(), None);
+    }
+
+    #[test]
+    fn truncate_by_display_width() {
+        assert_eq!(truncate("abcdef", 6), "abcdef");
+        assert_eq!(truncate("abcdefg", 6), "abcde…");
+        assert_eq!(truncate("端口状态很好", 12), "端口状态很好");
+        assert_eq!(truncate("端口状态很好", 7), "端口状…");
+        assert_eq!(truncate("端口状态很好", 6), "端口…");
+    }
+
+    #[test]
+    fn wide_characters_aligned() {
+        let headers = ["name".to_string(), "state".to_string()];
+        let rows = [vec!["端口".to_string(), "up".to_string()], vec!["eth0".to_string(), "down".to_string()]];
+        let table = render_table(&headers, &rows, None);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines[2], "端口  up");
+        assert_eq!(lines[3], "eth0  down");
+    }
+}
@@ -0,0 +1,337 @@
+//! Help pages of commands, wrapped to the width of terminal.
+//!
+//! Besides, the `?` key shows the possible next words of the line being
+//! edited, see `HelpKey`.
+
+use std::io::{stdout, IsTerminal};
+use std::sync::{Arc, Mutex};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+
+use crate::parser::{self, Token};
+use crate::Command;
+
+/// Width of the help text, when it is not shown on a terminal.
+const DEFAULT_WIDTH: usize = 80;
+
+/// Width of the labels, e.g. "Command         : ".
+const LABEL_WIDTH: usize = 18;
+
+/// Width of the names in lists, e.g. "  tree            : ".
+const NAME_WIDTH: usize = 20;
+
+/// Get the width to wrap the help text at.
+fn text_width() -> usize {
+    let width = if stdout().is_terminal() {
+        crossterm::terminal::size().ok().map(|(w, _)| w as usize).filter(|w| *w > 0)
+    } else {
+        None
+    };
+    width.unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Wrap text into lines no wider than width, with all lines but the first
+/// indented. Newlines in text start new paragraphs.
+fn wrap(text: &str, indent: usize, width: usize) -> String {
+    // leave some room for the text, even on a narrow terminal
+    let room = width.saturating_sub(indent).max(20);
+    let mut out = String::new();
+    let mut first = true;
+
+    for paragraph in text.split('\n') {
+        let mut len = 0;
+        if !first {
+            out.push('\n');
+        }
+        for word in paragraph.split_whitespace() {
+            let n = word.chars().count();
+            if len > 0 && len + 1 + n > room {
+                out.push('\n');
+                len = 0;
+            }
+            if len == 0 {
+                if !first {
+                    out.push_str(&" ".repeat(indent));
+                }
+            } else {
+                out.push(' ');
+                len += 1;
+            }
+            out.push_str(word);
+            len += n;
+            first = false;
+        }
+        first = false;
+    }
+    out
+}
+
+/// Make a labeled line, e.g. "Usage           : tree".
+fn labeled(label: &str, text: &str, width: usize) -> String {
+    format!("{:16}: {}\n", label, wrap(text, LABEL_WIDTH, width))
+}
+
+/// Make an item of a list, e.g. "  tree            : prints the whole command tree".
+fn item(name: &str, text: &str, width: usize) -> String {
+    format!("  {:16}: {}\n", name, wrap(text, NAME_WIDTH, width))
+}
+
+/// Render the help page of cmd, with name shown as the command, which can be
+/// the full path of it.
+pub(crate) fn command_help(cmd: &Command, name: &str) -> String {
+    let width = text_width();
+    let mut s = String::new();
+
+    let mut title = name.to_string();
+    if let Some(alias) = cmd.alias.as_ref() {
+        title = format!("{}, {}", title, alias);
+    }
+    s += &labeled("Command", &title, width);
+    s += &labeled("Usage", cmd.usage.unwrap_or(name), width);
+    s += &labeled("Description", cmd.about.unwrap_or(""), width);
+    if let Some(deprecated) = cmd.deprecated {
+        s += &labeled("Deprecated", deprecated, width);
+    }
+    if cmd.experimental {
+        s += &labeled("Experimental", "yes", width);
+    }
+
+    if let Some(long_about) = cmd.long_about {
+        s += &format!("\n  {}\n", wrap(long_about, 2, width));
+    }
+    if !cmd.args.is_empty() {
+        s += "\nArguments\n";
+        for (arg, help) in &cmd.args {
+            s += &item(arg, help, width);
+        }
+    }
+    if !cmd.flags.is_empty() {
+        s += "\nFlags\n";
+        for (flag, help) in &cmd.flags {
+            s += &item(flag, help, width);
+        }
+    }
+    if !cmd.examples.is_empty() {
+        s += "\nExamples\n";
+        for (example, help) in &cmd.examples {
+            s += &format!("  # {}\n  {}\n", wrap(help, 4, width), example);
+        }
+    }
+    if !cmd.see_also.is_empty() {
+        s += "\n";
+        s += &labeled("See also", &cmd.see_also.join(", "), width);
+    }
+    s
+}
+
+/// Render the list of subcommands of cmd.
+pub(crate) fn subcommand_help(cmd: &Command) -> String {
+    let width = text_width();
+    let mut s = String::from("SubCommands\n");
+    for sub in &cmd.subcommands {
+        s += &item(&sub.get_description(), sub.about.unwrap_or(&sub.name), width);
+    }
+    s
+}
+
+/// The global options of the program, see `App::run_from_args`.
+const PROGRAM_OPTIONS: [(&str, &str); 5] = [
+    ("--script <file>", "executes the commands in the file"),
+    ("--command <line>", "executes the command line, which may have variables, ';', '&&', '||' etc."),
+    ("--no-history", "doesn't load or save the history of the CLI loop"),
+    ("-h, --help", "shows this help"),
+    ("--version", "shows the version"),
+];
+
+/// Render the help of the program, with the global options and the commands,
+/// for `--help`.
+pub(crate) fn program_help(tree: &Command, program: &str) -> String {
+    let width = text_width();
+    let mut s = labeled("Usage", &format!("{} [options] [command [args...]]", program), width);
+    s += "\nOptions\n";
+    for (name, text) in PROGRAM_OPTIONS {
+        s += &item(name, text, width);
+    }
+    s += "\n";
+    s += &subcommand_help(tree);
+    s
+}
+
+/// Render the help page of cmd, along with the list of its subcommands if any.
+pub(crate) fn full_help(cmd: &Command, name: &str) -> String {
+    let mut s = String::new();
+    // the root command has no name, and nothing but subcommands
+    if !name.is_empty() {
+        s += &command_help(cmd, name);
+    }
+    if !cmd.subcommands.is_empty() {
+        if !s.is_empty() {
+            s += "\n";
+        }
+        s += &subcommand_help(cmd);
+    }
+    s
+}
+
+/// Render the help pages of all commands in the tree, for `help -a`.
+pub(crate) fn reference(tree: &Command) -> String {
+    let mut s = String::new();
+    tree.for_each("", &mut |cmd, path| {
+        if path.is_empty() {
+            return;
+        }
+        if !s.is_empty() {
+            s += "\n";
+        }
+        // path is like "/terminal/length"
+        s += &command_help(cmd, &path[1..].replace('/', " "));
+    });
+    s
+}
+
+/// Add the lines of the subcommands of cmd to lines, with the connectors
+/// prefixed, down to depth levels.
+fn tree_lines<'c>(
+    cmd: &'c Command,
+    prefix: &str,
+    level: usize,
+    depth: Option<usize>,
+    lines: &mut Vec<(String, Option<&'c str>)>,
+) {
+    if depth.is_some_and(|d| level > d) {
+        return;
+    }
+
+    let n = cmd.subcommands.len();
+    for (i, sub) in cmd.subcommands.iter().enumerate() {
+        let last = i + 1 == n;
+        let connector = if last { "└── " } else { "├── " };
+        lines.push((format!("{}{}{}", prefix, connector, sub.get_description().trim_end()), sub.about));
+
+        let indent = if last { "    " } else { "│   " };
+        tree_lines(sub, &format!("{}{}", prefix, indent), level + 1, depth, lines);
+    }
+}
+
+/// Render cmd and its subcommands as a tree, with name shown as the root,
+/// down to depth levels, and with the descriptions if verbose.
+pub(crate) fn tree(cmd: &Command, name: &str, depth: Option<usize>, verbose: bool) -> String {
+    let mut lines = vec![(name.to_string(), cmd.about)];
+    tree_lines(cmd, "", 1, depth, &mut lines);
+
+    let width = lines.iter().map(|(line, _)| line.chars().count()).max().unwrap_or(0);
+    let mut s = String::new();
+    for (line, about) in lines {
+        match about {
+            Some(about) if verbose => {
+                let pad = width - line.chars().count();
+                s += &format!("{}{}  {}\n", line, " ".repeat(pad), about);
+            }
+            _ => s += &format!("{}\n", line),
+        }
+    }
+    s
+}
+
+/// Render the possible next words of the partial line, for the `?` key.
+///
+/// The words typed are resolved by `Command::locate_subcommand`, the last
+/// word is taken as a prefix unless it is followed by a space.
+pub(crate) fn context_help(tree: &Command, text: &str) -> String {
+    let tokens = parser::tokenize(text).unwrap_or_default();
+    // only the last command of a chain, e.g. 'jobs; fg ?'
+    let start = tokens.iter().rposition(|t| matches!(t.0, Token::Op(_))).map_or(0, |i| i + 1);
+    let mut words: Vec<&str> = tokens[start..]
+        .iter()
+        .filter_map(|t| match &t.0 {
+            Token::Word(w) => Some(w.as_str()),
+            Token::Op(_) => None,
+        })
+        .collect();
+    let partial = if text.ends_with(char::is_whitespace) {
+        ""
+    } else {
+        words.pop().unwrap_or("")
+    };
+
+    // the deepest command located, the rest of the words are its arguments
+    let (cmd, nargs) = (0..=words.len())
+        .rev()
+        .find_map(|n| tree.locate_subcommand(&words[..n]).map(|cmd| (cmd, words.len() - n)))
+        .unwrap_or((tree, words.len()));
+
+    let width = text_width();
+    let mut s = String::new();
+    if nargs == 0 {
+        for sub in &cmd.subcommands {
+            if sub.name.starts_with(partial) || sub.alias.as_ref().is_some_and(|a| a.starts_with(partial)) {
+                s += &item(&sub.get_description(), sub.about.unwrap_or(&sub.name), width);
+            }
+        }
+    }
+    if cmd.action.is_some() {
+        if !partial.starts_with('-') {
+            for (arg, help) in &cmd.args {
+                s += &item(arg, help, width);
+            }
+        }
+        for (flag, help) in cmd.flags.iter().filter(|(flag, _)| flag.starts_with(partial)) {
+            s += &item(flag, help, width);
+        }
+        if cmd.args.is_empty() {
+            if let Some(usage) = cmd.usage {
+                s += &item("usage", usage, width);
+            }
+        }
+        if partial.is_empty() {
+            s += &item("<cr>", "executes the command", width);
+        }
+    }
+
+    if s.is_empty() {
+        s = format!("Unrecognized command {:?}\n", text.trim());
+    }
+    s
+}
+
+/// What the `?` key asks for, shared by `HelpKey` and the CLI loop.
+#[derive(Default)]
+pub(crate) struct HelpRequest {
+    /// The lines entered before, when the line being edited is a continuation.
+    pub(crate) previous: String,
+    /// The line being edited, and the cursor position, when `?` is pressed.
+    pub(crate) line: Option<(String, usize)>,
+}
+
+impl HelpRequest {
+    /// Get the text of the input, with line joined to the previous lines.
+    pub(crate) fn text(&self, line: &str) -> String {
+        if self.previous.is_empty() {
+            return line.to_string();
+        }
+        let mut text = self.previous.clone();
+        parser::join_lines(&mut text, line);
+        text
+    }
+}
+
+/// Handler of the `?` key.
+///
+/// It interrupts the line editing, with the line saved in the request, so that
+/// the CLI loop can print the help and edit the line again. In quotes or after
+/// a `\`, `?` is inserted as it is.
+pub(crate) struct HelpKey(pub(crate) Arc<Mutex<HelpRequest>>);
+
+impl ConditionalEventHandler for HelpKey {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        let mut request = self.0.lock().unwrap();
+        let line = ctx.line();
+        let text = request.text(&line[..ctx.pos()]);
+        if parser::check_input(&text).is_incomplete() {
+            return None;
+        }
+
+        request.line = Some((line.to_string(), ctx.pos()));
+        Some(Cmd::Interrupt)
+    }
+}
@@ -0,0 +1,262 @@
+//! Background jobs.
+//!
+//! A command line ending with `&` is dispatched to a worker thread. Its output
+//! is buffered and shown by `fg`, and it can be asked to stop by `kill`. Since
+//! there is no way to abort a thread, cancellation is cooperative: long running
+//! actions should check [`App::is_cancelled`] and return early.
+//!
+//! A job done is removed from the job table by `fg`, or purged when there are
+//! too many of them, after it has been reported.
+//!
+//! `fg` and `wait` on a terminal can be interrupted by any key, e.g. Ctrl+C,
+//! which leaves the job running in background.
+//!
+//! [`App::is_cancelled`]: crate::App::is_cancelled
+
+use std::cell::RefCell;
+use std::io::{stdin, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::output::{self, OutputBuffer};
+use crate::pager::RawMode;
+use crate::{script, watch, CmdExeCode, XcliError, XcliResult};
+
+/// The number of jobs done and reported which are kept for `fg`, the oldest
+/// ones beyond it are purged.
+const MAX_FINISHED_JOBS: usize = 16;
+
+/// How often a job in foreground is checked, for its output and the keys pressed.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    static CURRENT_JOB: RefCell<Option<Arc<Job>>> = const { RefCell::new(None) };
+}
+
+/// The state of a job.
+pub(crate) enum JobState {
+    Running,
+    Done(XcliResult),
+}
+
+/// A command line running in background.
+pub(crate) struct Job {
+    pub(crate) id: usize,
+    pub(crate) line: String,
+    pub(crate) output: OutputBuffer,
+    cancelled: AtomicBool,
+    reported: AtomicBool,
+    state: Mutex<JobState>,
+    cond: Condvar,
+}
+
+impl Job {
+    fn new(id: usize, line: &str) -> Self {
+        Job {
+            id,
+            line: line.to_string(),
+            output: OutputBuffer::default(),
+            cancelled: AtomicBool::new(false),
+            reported: AtomicBool::new(false),
+            state: Mutex::new(JobState::Running),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Ask the job to stop.
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), JobState::Done(_))
+    }
+
+    /// Mark the job as done with the result of its action.
+    pub(crate) fn finish(&self, ret: XcliResult) {
+        *self.state.lock().unwrap() = JobState::Done(ret);
+        self.cond.notify_all();
+    }
+
+    /// Wait for the job to be done, or until timeout. Returns true if done.
+    pub(crate) fn wait_timeout(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .cond
+            .wait_timeout_while(state, timeout, |s| matches!(s, JobState::Running))
+            .unwrap();
+        matches!(*state, JobState::Done(_))
+    }
+
+    /// Get a copy of the result of the job, if it is done. `exit` in a job
+    /// doesn't end the session, so it is taken as `Ok`.
+    pub(crate) fn result(&self) -> Option<XcliResult> {
+        match &*self.state.lock().unwrap() {
+            JobState::Running => None,
+            JobState::Done(Ok(_)) => Some(Ok(CmdExeCode::Ok)),
+            JobState::Done(Err(err)) => Some(Err(err.copy())),
+        }
+    }
+
+    /// A short description of the job status, as shown by `jobs`.
+    pub(crate) fn status(&self) -> String {
+        match &*self.state.lock().unwrap() {
+            JobState::Running if self.is_cancelled() => "Cancelling".to_string(),
+            JobState::Running => "Running".to_string(),
+            JobState::Done(_) if self.is_cancelled() => "Killed".to_string(),
+            JobState::Done(Ok(_)) => "Done".to_string(),
+            JobState::Done(Err(err)) => format!("Failed: {}", err),
+        }
+    }
+
+    /// Make this job the current job of the calling thread.
+    pub(crate) fn enter(self: &Arc<Self>) {
+        CURRENT_JOB.with(|j| *j.borrow_mut() = Some(self.clone()));
+    }
+
+    /// Clear the current job of the calling thread.
+    pub(crate) fn leave() {
+        CURRENT_JOB.with(|j| *j.borrow_mut() = None);
+    }
+
+    /// Whether the calling thread is running a background job.
+    pub(crate) fn in_background() -> bool {
+        CURRENT_JOB.with(|j| j.borrow().is_some())
+    }
+
+    /// Whether the job running on the calling thread has been cancelled.
+    pub(crate) fn current_cancelled() -> bool {
+        CURRENT_JOB.with(|j| j.borrow().as_ref().is_some_and(|job| job.is_cancelled()))
+    }
+}
+
+/// The job table of a CLI session.
+#[derive(Default)]
+pub(crate) struct Jobs {
+    next_id: Mutex<usize>,
+    table: Mutex<Vec<Arc<Job>>>,
+    spawner: Mutex<Option<Sender<Arc<Job>>>>,
+}
+
+impl Jobs {
+    /// Install the channel to the job spawner, available while the CLI loop is running.
+    pub(crate) fn set_spawner(&self, spawner: Option<Sender<Arc<Job>>>) {
+        *self.spawner.lock().unwrap() = spawner;
+    }
+
+    /// Submit a command line to be run in background, returns the job id.
+    pub(crate) fn submit(&self, line: &str) -> Option<usize> {
+        let spawner = self.spawner.lock().unwrap();
+        let spawner = spawner.as_ref()?;
+
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let job = Arc::new(Job::new(*next_id, line));
+        spawner.send(job.clone()).ok()?;
+        let mut table = self.table.lock().unwrap();
+        table.push(job);
+        purge(&mut table);
+        Some(*next_id)
+    }
+
+    /// Get a job by its id.
+    pub(crate) fn get(&self, id: usize) -> Option<Arc<Job>> {
+        self.table.lock().unwrap().iter().find(|j| j.id == id).cloned()
+    }
+
+    /// Get all jobs.
+    pub(crate) fn all(&self) -> Vec<Arc<Job>> {
+        self.table.lock().unwrap().clone()
+    }
+
+    /// Remove a job from the table.
+    pub(crate) fn remove(&self, id: usize) {
+        self.table.lock().unwrap().retain(|j| j.id != id);
+    }
+
+    /// Take the jobs done but not yet reported.
+    pub(crate) fn take_finished(&self) -> Vec<Arc<Job>> {
+        self.all()
+            .into_iter()
+            .filter(|j| j.is_done() && !j.reported.swap(true, Ordering::SeqCst))
+            .collect()
+    }
+
+    /// Ask all running jobs to stop.
+    pub(crate) fn cancel_all(&self) {
+        for job in self.table.lock().unwrap().iter() {
+            job.cancel();
+        }
+    }
+}
+
+/// Remove the oldest jobs done and reported, beyond `MAX_FINISHED_JOBS`.
+fn purge(table: &mut Vec<Arc<Job>>) {
+    let finished = |j: &Arc<Job>| j.is_done() && j.reported.load(Ordering::SeqCst);
+    let mut excess = table.iter().filter(|j| finished(j)).count().saturating_sub(MAX_FINISHED_JOBS);
+    table.retain(|j| {
+        if excess > 0 && finished(j) {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+/// Wait for the job to be done, with its output shown as it comes if
+/// show_output, returns whether it is done.
+///
+/// On a terminal, any key, e.g. Ctrl+C, stops waiting, while the job is left
+/// running in background.
+pub(crate) fn wait_foreground(job: &Job, show_output: bool) -> bool {
+    let interactive = !output::is_capturing() && stdin().is_terminal();
+    // in raw mode while waiting, so Ctrl+C is read as a key instead of killing the CLI
+    let mut raw = if interactive { RawMode::enable().ok() } else { None };
+    loop {
+        let done = job.wait_timeout(POLL_INTERVAL);
+        if show_output {
+            let output = std::mem::take(&mut *job.output.lock().unwrap());
+            if !output.is_empty() {
+                // out of raw mode while printing, as the output has no '\r' in it
+                raw = None;
+                xprint!("{}", String::from_utf8_lossy(&output));
+                if interactive {
+                    raw = RawMode::enable().ok();
+                }
+            }
+        }
+        if done {
+            return true;
+        }
+        if raw.is_some() && watch::key_pressed(POLL_INTERVAL) {
+            return false;
+        }
+    }
+}
+
+/// The error of waiting for the job interrupted.
+pub(crate) fn interrupted(job: &Job) -> XcliError {
+    XcliError::Other(format!("Interrupted, [{}] is left running in background", job.id))
+}
+
+/// Consume the output of job, and remove it from the job table once done.
+///
+/// Returns the result of the job.
+pub(crate) fn foreground(jobs: &Jobs, job: &Job) -> XcliResult {
+    if !wait_foreground(job, true) {
+        return Err(interrupted(job));
+    }
+    jobs.remove(job.id);
+    let ret = job.result().unwrap_or(Ok(CmdExeCode::Ok));
+    // the error is in the output of the job
+    if ret.is_err() {
+        script::set_reported();
+    }
+    ret
+}
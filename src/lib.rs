@@ -10,11 +10,12 @@
 //! * MacOS (not tested yet)
 //!
 //! **Note**:
-//! * " quoted argument is not supported
+//! * Arguments can be quoted by ' or ", and `\` escapes the next character
+//! * Unquoted `;`, `&&`, `||` and `&` are operators even inside an argument, and `#` starts a comment at the beginning of one
 //! * No prompt is shown when running on non-tty device. Need a simple tweak on Rustyline...
 //!
 //! ## Example
-//! ```rust
+//! ```no_run
 //! use xcli::*;
 //!
 //!
@@ -43,25 +44,70 @@
 //! xcli = "0.5"
 //! ```
 
-use std::cell::RefCell;
+use std::cell::Cell;
 use std::fmt::Debug;
 use std::ops::Add;
-use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use log::{debug, info, LevelFilter};
 
 use rustyline::completion::Completer;
 use rustyline::config::CompletionType;
 use rustyline::config::Configurer;
-use rustyline::{EditMode, Editor};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::error::ReadlineError;
+use rustyline::{EditMode, Editor, EventHandler, KeyEvent};
 
-use rustyline_derive::{Helper, Highlighter, Hinter, Validator};
+use rustyline_derive::{Helper, Highlighter, Hinter};
 use std::collections::HashMap;
-use std::io::{BufWriter, Write};
+use std::io::{stdout, IsTerminal};
 
 use std::result::Result as stdResult;
 
+pub use crate::args::Args;
+pub use crate::audit::Audit;
+pub use crate::derive::{Execute, XcliCommand};
+pub use crate::doc::DocFormat;
+pub use crate::format::{OutputFormat, Table};
+pub use crate::middleware::Invocation;
+pub use crate::status::ExitStatus;
+pub use crate::tree::CommandTree;
+use crate::job::{Job, Jobs};
+use crate::middleware::{AfterHook, AroundHook, BeforeHook, Middleware};
+use crate::parser::Completeness;
+
+/// Derive `XcliCommand`, see the trait for details.
+#[cfg(feature = "derive")]
+pub use xcli_derive::XcliCommand;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::derive::{Dispatch, Parsed};
+}
+
+#[macro_use]
+pub mod output;
+mod args;
+mod audit;
+mod derive;
+mod doc;
+mod format;
+mod help;
+mod job;
+mod middleware;
+mod pager;
+mod parser;
+mod prompt;
+mod schema;
+mod script;
+mod status;
+mod tree;
+mod watch;
+
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum XcliError {
     /// Bad command syntax.
     #[error("Bad syntax")]
@@ -75,12 +121,146 @@ pub enum XcliError {
     /// Bad argument.
     #[error("Bad argument: {0}")]
     BadArgument(String),
+    /// A required argument is missing, with its name.
+    #[error("Missing required argument: {0}")]
+    RequiredArgument(String),
+    /// An argument can't be parsed into the type expected.
+    #[error("Bad argument: {name}, {value:?} is not a valid {expected}, {reason}")]
+    InvalidArgument {
+        name: String,
+        value: String,
+        expected: String,
+        reason: String,
+    },
     /// Mismatched arguments.
     #[error("Mismatched argument(s): wanted: {0}, actual: {1}")]
     MismatchArgument(usize, usize),
+    /// The privilege level of the session is too low for the command.
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    /// No such command, or arguments given to a command without action.
+    #[error("Unknown command or arguments: {0}")]
+    UnknownCommand(String),
     /// Other error.
     #[error("{0}")]
     Other(String),
+    /// An error of the application, e.g. an I/O error, which is the `source()`.
+    #[error("{0}")]
+    Custom(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl From<std::io::Error> for XcliError {
+    fn from(err: std::io::Error) -> Self {
+        XcliError::Custom(Box::new(err))
+    }
+}
+
+impl XcliError {
+    /// The kind of this error, aka. the name of the variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            XcliError::BadSyntax => "BadSyntax",
+            XcliError::MissingHandler(_) => "MissingHandler",
+            XcliError::MissingArgument => "MissingArgument",
+            XcliError::BadArgument(_) => "BadArgument",
+            XcliError::RequiredArgument(_) => "RequiredArgument",
+            XcliError::InvalidArgument { .. } => "InvalidArgument",
+            XcliError::MismatchArgument(_, _) => "MismatchArgument",
+            XcliError::PermissionDenied(_) => "PermissionDenied",
+            XcliError::UnknownCommand(_) => "UnknownCommand",
+            XcliError::Other(_) => "Other",
+            XcliError::Custom(_) => "Custom",
+        }
+    }
+
+    /// Wrap an error of the application, or a message, e.g.
+    /// `XcliError::custom(err)` or `XcliError::custom("port is down")`.
+    pub fn custom<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> Self {
+        XcliError::Custom(err.into())
+    }
+
+    /// The exit code of this error, when the CLI exits for it.
+    ///
+    /// | Error | Code |
+    /// |-------|------|
+    /// | `Other`, `Custom` | 1 |
+    /// | `BadSyntax` | 2 |
+    /// | `MissingArgument`, `RequiredArgument`, `MismatchArgument` | 64, bad usage |
+    /// | `BadArgument`, `InvalidArgument` | 65, bad input data |
+    /// | `MissingHandler` | 70, internal error |
+    /// | `PermissionDenied` | 77, permission denied |
+    /// | `UnknownCommand` | 127, command not found |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            XcliError::Other(_) | XcliError::Custom(_) => 1,
+            XcliError::BadSyntax => 2,
+            XcliError::MissingArgument | XcliError::RequiredArgument(_) | XcliError::MismatchArgument(_, _) => 64,
+            XcliError::BadArgument(_) | XcliError::InvalidArgument { .. } => 65,
+            XcliError::MissingHandler(_) => 70,
+            XcliError::PermissionDenied(_) => 77,
+            XcliError::UnknownCommand(_) => 127,
+        }
+    }
+
+    /// A copy of this error, as errors can't be cloned, with the error wrapped
+    /// by `Custom` turned into its message.
+    pub(crate) fn copy(&self) -> Self {
+        match self {
+            XcliError::BadSyntax => XcliError::BadSyntax,
+            XcliError::MissingHandler(s) => XcliError::MissingHandler(s.clone()),
+            XcliError::MissingArgument => XcliError::MissingArgument,
+            XcliError::BadArgument(s) => XcliError::BadArgument(s.clone()),
+            XcliError::RequiredArgument(s) => XcliError::RequiredArgument(s.clone()),
+            XcliError::InvalidArgument {
+                name,
+                value,
+                expected,
+                reason,
+            } => XcliError::InvalidArgument {
+                name: name.clone(),
+                value: value.clone(),
+                expected: expected.clone(),
+                reason: reason.clone(),
+            },
+            XcliError::MismatchArgument(wanted, actual) => XcliError::MismatchArgument(*wanted, *actual),
+            XcliError::PermissionDenied(s) => XcliError::PermissionDenied(s.clone()),
+            XcliError::UnknownCommand(s) => XcliError::UnknownCommand(s.clone()),
+            XcliError::Other(s) => XcliError::Other(s.clone()),
+            XcliError::Custom(err) => XcliError::Custom(err.to_string().into()),
+        }
+    }
+
+    /// Whether the usage of the command should be shown with this error,
+    /// i.e. the command is not used properly.
+    pub fn is_usage_error(&self) -> bool {
+        !matches!(
+            self,
+            XcliError::PermissionDenied(_) | XcliError::Other(_) | XcliError::Custom(_)
+        )
+    }
+}
+
+/// Report an error, with the command which failed, if any. This is the
+/// default hook of `App::on_error`.
+///
+/// The error is printed with its sources, then the usage of the command if
+/// it is not used properly.
+pub fn report_error(_app: &App, err: &XcliError, cmd: Option<&Command>) {
+    xprintln!("{}", err);
+    // the source of Custom is the error wrapped, which is printed already
+    let mut source = std::error::Error::source(err);
+    if let XcliError::Custom(_) = err {
+        source = source.and_then(|e| e.source());
+    }
+    while let Some(e) = source {
+        xprintln!("  caused by: {}", e);
+        source = e.source();
+    }
+
+    if let Some(cmd) = cmd.filter(|_| err.is_usage_error()) {
+        xprintln!();
+        cmd.show_command_usage();
+    }
 }
 
 /// Cmd action execute result
@@ -90,6 +270,31 @@ pub type XcliResult = stdResult<CmdExeCode, XcliError>;
 /// This avoids an allocation of Box::new.
 type CmdAction = fn(&App, &[&str]) -> XcliResult;
 
+/// The hook to check the password to enable a privilege level.
+type EnableHook = fn(&App, u8, &str) -> bool;
+
+/// How long the CLI loop waits for background jobs to stop when it ends.
+const JOB_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The highest privilege level, which `enable` enters by default.
+pub const MAX_PRIVILEGE: u8 = 15;
+
+/// Logged instead of a command line which can't be redacted, see `App::redact_line`.
+const UNREDACTED: &str = "<not shown, it may have sensitive arguments>";
+
+/// How many times a command can be redirected by `replaced_by` in a row,
+/// e.g. two commands replaced by each other.
+const MAX_REDIRECTS: usize = 8;
+
+thread_local! {
+    /// The number of redirects by `replaced_by` of the command running on this thread.
+    static REDIRECTS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The hook to report errors, with the command which failed, if the error
+/// is returned by a command.
+type ErrorHook = fn(&App, &XcliError, Option<&Command>);
+
 /// The return code of Command action.
 #[derive(Debug, PartialEq, Eq)]
 pub enum CmdExeCode {
@@ -99,25 +304,54 @@ pub enum CmdExeCode {
     Exit,
 }
 
-type IAny = Box<dyn std::any::Any>;
+type IAny = Box<dyn std::any::Any + Send + Sync>;
 
 /// Xcli object
 pub struct App<'a> {
     pub(crate) name: String,
     pub(crate) version: Option<&'a str>,
     pub(crate) author: Option<&'a str>,
-    pub(crate) tree: Command<'a>,
-    pub(crate) rl: Rc<RefCell<Editor<PrefixCompleter>>>,
-    pub(crate) handlers: HashMap<String, IAny>,
+    pub(crate) tree: CommandTree<'a>,
+    /// The line editor, taken out while reading a line, so that it is not locked meanwhile.
+    pub(crate) rl: Mutex<Option<Editor<PrefixCompleter>>>,
+    pub(crate) edit_mode: Mutex<EditMode>,
+    pub(crate) jobs: Jobs,
+    pub(crate) vars: Mutex<HashMap<String, String>>,
+    pub(crate) last_status: Mutex<String>,
+    pub(crate) output_format: Mutex<OutputFormat>,
+    pub(crate) terminal_length: Mutex<Option<usize>>,
+    pub(crate) help_request: Arc<Mutex<help::HelpRequest>>,
+    pub(crate) error_hook: ErrorHook,
+    pub(crate) status: Mutex<ExitStatus>,
+    pub(crate) history: bool,
+    pub(crate) middleware: Middleware,
+    pub(crate) audit: Option<Audit>,
+    pub(crate) privilege: Arc<Mutex<u8>>,
+    pub(crate) enable_hook: Option<EnableHook>,
 }
 
 /// Command structure, which describes a command and its action.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Command<'a> {
     pub(crate) name: String,
     pub(crate) alias: Option<String>,
     pub(crate) about: Option<&'a str>,
     pub(crate) usage: Option<&'a str>,
+    pub(crate) long_about: Option<&'a str>,
+    pub(crate) args: Vec<(&'a str, &'a str)>,
+    pub(crate) flags: Vec<(&'a str, &'a str)>,
+    pub(crate) examples: Vec<(&'a str, &'a str)>,
+    pub(crate) see_also: Vec<&'a str>,
+    pub(crate) sensitive_args: Vec<usize>,
+    pub(crate) sensitive_flags: Vec<&'a str>,
+    pub(crate) privilege: u8,
+    pub(crate) confirm: Option<&'a str>,
+    pub(crate) output_option: bool,
+    pub(crate) disabled: bool,
+    pub(crate) hidden: bool,
+    pub(crate) deprecated: Option<&'a str>,
+    pub(crate) replaced_by: Option<&'a str>,
+    pub(crate) experimental: bool,
     pub(crate) subcommands: Vec<Command<'a>>,
     pub(crate) action: Option<CmdAction>,
 }
@@ -129,12 +363,16 @@ impl<'a> App<'a> {
         let builtin_cmds =
             Command::new("")
                 .about("Interactive CLI")
-                .subcommand(Command::new("tree").about("prints the whole command tree").usage("tree").action(
-                    |app: &App, _| -> XcliResult {
-                        app.show_tree();
-                        Ok(CmdExeCode::Ok)
-                    },
-                ))
+                .subcommand(
+                    Command::new("tree")
+                        .about("prints the whole command tree")
+                        .usage("tree [command...] [--depth N] [--verbose] [--json]")
+                        .arg("command...", "shows the subtree of the command only, e.g. 'tree terminal'")
+                        .flag("--depth N", "shows N levels of subcommands at most")
+                        .flag("--verbose", "shows the descriptions of commands as well")
+                        .flag("--json", "prints the tree as JSON, for external tools to introspect the CLI")
+                        .action(cli_tree),
+                )
                 .subcommand(
                     Command::new("mode")
                         .about("manages the line editor mode, vi/emcas")
@@ -150,30 +388,165 @@ impl<'a> App<'a> {
                 .subcommand(
                     Command::new_with_alias("help", "h")
                         .about("displays help information")
-                        .usage("help [command]")
+                        .usage("help [-a | command...]")
+                        .long_about(
+                            "'<command> --help' shows the help of the command as well, \
+                             and so does '<command> ?' for a command without action, e.g. 'terminal ?'.",
+                        )
+                        .arg("command...", "the command to show help for, e.g. 'help terminal length'")
+                        .flag("-a", "shows the help of all commands, as a full reference")
                         .action(cli_help),
                 )
+                .subcommand(
+                    Command::new("enable")
+                        .about("enters a higher privilege level, with the password")
+                        .usage("enable [level]")
+                        .arg("[level]", "the privilege level, 15 by default")
+                        .see_also("disable")
+                        .action(cli_enable),
+                )
+                .subcommand(
+                    Command::new("disable")
+                        .about("leaves the privilege level, back to a lower one")
+                        .usage("disable [level]")
+                        .arg("[level]", "the privilege level, 0 by default")
+                        .see_also("enable")
+                        .action(cli_disable),
+                )
                 .subcommand(
                     Command::new("exit")
                         .about("quits CLI and exits to shell")
-                        .action(|_, _| -> XcliResult { Ok(CmdExeCode::Exit) }),
+                        .usage("exit [code]")
+                        .long_about("In a command substitution or a background job, it ends that only, with the code ignored.")
+                        .arg("[code]", "the exit code, by default the one of the last command, 0 if it succeeded")
+                        .action(cli_exit),
                 )
                 .subcommand(Command::new_with_alias("version", "v").about("shows version information").action(
                     |app, _| -> XcliResult {
-                        println!("{}\n{}\n{}\n", app.get_name(), app.get_author(), app.get_version());
+                        xprintln!("{}\n{}\n{}\n", app.get_name(), app.get_author(), app.get_version());
                         Ok(CmdExeCode::Ok)
                     },
-                ));
-
-        let rl = Rc::new(RefCell::new(Editor::<PrefixCompleter>::new()));
+                ))
+                .subcommand(
+                    Command::new("jobs")
+                        .about("lists background jobs, started by 'command &'")
+                        .usage("jobs")
+                        .action(cli_jobs),
+                )
+                .subcommand(
+                    Command::new("fg")
+                        .about("shows the output of a background job, waiting for it to be done")
+                        .usage("fg [job-id]")
+                        .action(cli_fg),
+                )
+                .subcommand(
+                    Command::new("wait")
+                        .about("waits for background jobs to be done")
+                        .usage("wait [job-id]")
+                        .action(cli_wait),
+                )
+                .subcommand(
+                    Command::new("kill")
+                        .about("asks a background job to stop")
+                        .usage("kill <job-id>")
+                        .action(cli_kill),
+                )
+                .subcommand(
+                    Command::new("echo")
+                        .about("prints the arguments, with variables and commands expanded")
+                        .usage("echo [args...]")
+                        .action(|_, args| -> XcliResult {
+                            xprintln!("{}", args.join(" "));
+                            Ok(CmdExeCode::Ok)
+                        }),
+                )
+                .subcommand(
+                    Command::new_with_alias("source", ".")
+                        .about("executes the commands in a script file")
+                        .usage("source <file>")
+                        .long_about(
+                            "The whole file is parsed first, so nothing is executed if there is a syntax error. \
+                             Then the commands are executed in order, as if they were typed in. \
+                             Lines beginning with '#' are comments. Scripts can be sourced by scripts, 16 levels at most.",
+                        )
+                        .arg("<file>", "the script file")
+                        .example("source setup.cli", "executes the commands in setup.cli")
+                        .action(cli_source),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("sets a session variable, which can be referred as $NAME or ${NAME}")
+                        .usage("set <NAME> [value...]")
+                        .arg("<NAME>", "the variable name, letters, digits and '_', not beginning with a digit")
+                        .arg("[value...]", "the value, words are joined by a space")
+                        .example("set port 3", "sets port to 3, then 'echo $port' prints 3")
+                        .see_also("unset")
+                        .see_also("vars")
+                        .action(cli_set),
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .about("removes session variables")
+                        .usage("unset <NAME...>")
+                        .action(cli_unset),
+                )
+                .subcommand(
+                    Command::new("vars")
+                        .about("lists all session variables")
+                        .usage("vars")
+                        .action(cli_vars),
+                )
+                .subcommand(
+                    Command::new("output")
+                        .about("manages the output format of structured data, or '--output <format>' of a command")
+                        .usage("output [table|json|yaml|csv]")
+                        .action(cli_output),
+                )
+                .subcommand(
+                    Command::new("terminal").about("manages the terminal settings").subcommand(
+                        Command::new("length")
+                            .about("sets the number of lines in a page of the pager, 0 to disable paging")
+                            .usage("terminal length [lines|auto]")
+                            .action(cli_terminal_length),
+                    ),
+                )
+                .subcommand(
+                    Command::new_with_alias("watch", "repeat")
+                        .about("executes a command periodically, until any key is pressed")
+                        .usage("watch [-n seconds] [-c count] <command...>")
+                        .long_about(
+                            "The output is redrawn on each run, with the changes highlighted. \
+                             When it is not in foreground, e.g. as a background job, the output of each run is printed.",
+                        )
+                        .arg("<command...>", "the command to execute")
+                        .flag("-n seconds", "interval between two runs, 2 seconds by default, a day at most")
+                        .flag("-c count", "stops after the command is executed count times")
+                        .example("watch -n 1 jobs", "lists background jobs every second")
+                        .action(watch::cli_watch),
+                );
+
+        let rl = Mutex::new(Some(Editor::<PrefixCompleter>::new()));
 
         App {
             name: n.into(),
             version: None,
             author: None,
-            tree: builtin_cmds,
+            tree: CommandTree::new(builtin_cmds),
             rl,
-            handlers: HashMap::default(),
+            edit_mode: Mutex::new(EditMode::Emacs),
+            jobs: Jobs::default(),
+            vars: Mutex::default(),
+            last_status: Mutex::new("Ok".to_string()),
+            output_format: Mutex::default(),
+            terminal_length: Mutex::default(),
+            help_request: Arc::default(),
+            error_hook: report_error,
+            status: Mutex::default(),
+            history: true,
+            middleware: Middleware::default(),
+            audit: None,
+            privilege: Arc::default(),
+            enable_hook: None,
         }
     }
 
@@ -203,9 +576,323 @@ impl<'a> App<'a> {
         self.version = Some(ver.into());
         self
     }
+
+    /// Set the hook to report errors, instead of `report_error`, which
+    /// prints the error and the usage of the command.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI").on_error(|app, err, cmd| {
+    ///     xprintln!("% {} (exit code {})", err, err.exit_code());
+    ///     if let Some(cmd) = cmd.filter(|_| err.is_usage_error()) {
+    ///         xprintln!("% try '{} --help'", cmd.get_name());
+    ///     }
+    /// });
+    /// ```
+    pub fn on_error(mut self, hook: ErrorHook) -> Self {
+        self.error_hook = hook;
+        self
+    }
+
+    /// Report an error by the hook set by `on_error`.
+    pub(crate) fn report_error(&self, err: &XcliError, cmd: Option<&Command>) {
+        (self.error_hook)(self, err, cmd)
+    }
+
+    /// Add a hook called before the action of each command, with the path and
+    /// arguments of it. The action is vetoed if the hook returns an error.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI").before(|app, cmd| match cmd.path() {
+    ///     ["reboot"] if app.get_var("ADMIN").is_none() => Err(XcliError::Other("Permission denied".into())),
+    ///     _ => Ok(()),
+    /// });
+    /// ```
+    pub fn before(mut self, hook: BeforeHook) -> Self {
+        self.middleware.before.push(hook);
+        self
+    }
+
+    /// Add a hook called after the action of each command, with the result
+    /// of it, or the error of the hook which vetoed it.
+    pub fn after(mut self, hook: AfterHook) -> Self {
+        self.middleware.after.push(hook);
+        self
+    }
+
+    /// Add a hook wrapping the action of each command, which executes the
+    /// action by calling `next`, or vetoes it by returning an error instead.
+    ///
+    /// The hook added first is the outermost one.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI").around(|_app, cmd, next| {
+    ///     let start = std::time::Instant::now();
+    ///     let ret = next();
+    ///     xprintln!("'{}' took {:?}", cmd.line(), start.elapsed());
+    ///     ret
+    /// });
+    /// ```
+    pub fn around(mut self, hook: AroundHook) -> Self {
+        self.middleware.around.push(hook);
+        self
+    }
+
+    /// Set the hook to check the password of `enable <level>`, which
+    /// can't enter a higher privilege level without it.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI").on_enable(|_app, _level, password| password == "secret");
+    /// ```
+    pub fn on_enable(mut self, hook: EnableHook) -> Self {
+        self.enable_hook = Some(hook);
+        self
+    }
+
+    /// Ask the user to confirm, with the message followed by `[y/N]`, returns
+    /// whether it is confirmed.
+    ///
+    /// It is refused in non-interactive mode, e.g. stdin is not a terminal, in
+    /// a background job, or in a sourced script.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// # fn cli_erase(app: &App, _args: &[&str]) -> XcliResult {
+    /// if !app.confirm("This will erase all data, continue?")? {
+    ///     return Ok(CmdExeCode::Ok);
+    /// }
+    /// # Ok(CmdExeCode::Ok)
+    /// # }
+    /// ```
+    pub fn confirm(&self, message: &str) -> stdResult<bool, XcliError> {
+        if !prompt::is_interactive() {
+            return Ok(false);
+        }
+        let answer = self.ask_line(&format!("{} [y/N] ", message), &["yes", "no"])?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes" | "YES"))
+    }
+
+    /// Ask the user for a line of input, after the prompt.
+    ///
+    /// The line is read by the editor of the session, so it works on non-tty
+    /// devices as well, but not in background jobs. It is not added to the history.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// # fn cli_user_add(app: &App, _args: &[&str]) -> XcliResult {
+    /// let name = app.ask("Name: ")?;
+    /// let password = app.ask_password("Password: ")?;
+    /// let shell = app.ask_with_default("Shell", "/bin/sh")?;
+    /// let role = app.choose("Role", &["admin", "operator", "guest"])?;
+    /// # Ok(CmdExeCode::Ok)
+    /// # }
+    /// ```
+    pub fn ask(&self, prompt: &str) -> stdResult<String, XcliError> {
+        self.ask_line(prompt, &[])
+    }
+
+    /// Ask the user for a password, which is not echoed, nor added to the history.
+    pub fn ask_password(&self, prompt: &str) -> stdResult<String, XcliError> {
+        if Job::in_background() {
+            return Err(XcliError::Other("Input is not available in background jobs".into()));
+        }
+        prompt::read_password(prompt)
+    }
+
+    /// Ask the user for a line of input, with the default value shown as
+    /// `prompt [default]: `, which is taken if nothing is entered.
+    pub fn ask_with_default(&self, prompt: &str, default: &str) -> stdResult<String, XcliError> {
+        let answer = self.ask_line(&format!("{} [{}]: ", prompt, default), &[])?;
+        if answer.trim().is_empty() {
+            return Ok(default.to_string());
+        }
+        Ok(answer)
+    }
+
+    /// Ask the user to choose one of the options, which are completed by `<tab>`.
+    ///
+    /// It is asked again if the answer is not an option, or it fails on
+    /// non-tty devices. It fails if there is no option at all.
+    pub fn choose<'o>(&self, prompt: &str, options: &[&'o str]) -> stdResult<&'o str, XcliError> {
+        if options.is_empty() {
+            return Err(XcliError::BadArgument("no option to choose".into()));
+        }
+        let prompt = format!("{} ({}): ", prompt, options.join("/"));
+        loop {
+            let answer = self.ask_line(&prompt, options)?;
+            if let Some(option) = options.iter().find(|&&o| o == answer.trim()) {
+                return Ok(option);
+            }
+            let err = XcliError::BadArgument(format!("{}, choose one of: {}", answer, options.join(", ")));
+            if !prompt::is_interactive() {
+                return Err(err);
+            }
+            xprintln!("{}", err);
+        }
+    }
+
+    /// Read a line by the editor, with the options completed.
+    fn ask_line(&self, prompt: &str, options: &[&str]) -> stdResult<String, XcliError> {
+        if Job::in_background() {
+            return Err(XcliError::Other("Input is not available in background jobs".into()));
+        }
+
+        let ret = self.with_editor(|rl| {
+            if let Some(helper) = rl.helper_mut() {
+                helper.asking = Some(options.iter().map(|o| o.to_string()).collect());
+            }
+            // '?' is taken literally, instead of showing help
+            let help_key = rl.unbind_sequence(KeyEvent::from('?'));
+            let ret = rl.readline(prompt);
+            if let Some(handler) = help_key {
+                rl.bind_sequence(KeyEvent::from('?'), handler);
+            }
+            if let Some(helper) = rl.helper_mut() {
+                helper.asking = None;
+            }
+            ret
+        })?;
+
+        match ret {
+            // the line read from a non-tty device has the newline kept
+            Ok(line) => Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string()),
+            Err(ReadlineError::Eof) => Err(XcliError::Other("Input is closed".into())),
+            Err(ReadlineError::Interrupted) => Err(XcliError::Other("Cancelled".into())),
+            Err(err) => Err(XcliError::Other(err.to_string())),
+        }
+    }
+
+    /// Take the editor out to use it, with the lock released meanwhile, so that
+    /// a background job doesn't block on the lock while a line is being read.
+    ///
+    /// It fails if the editor is taken already, e.g. by the CLI loop.
+    fn with_editor<R, F>(&self, f: F) -> stdResult<R, XcliError>
+    where
+        F: FnOnce(&mut Editor<PrefixCompleter>) -> R,
+    {
+        let mut rl = self
+            .rl
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| XcliError::Other("The line editor is busy".into()))?;
+        rl.set_edit_mode(*self.edit_mode.lock().unwrap());
+        let ret = f(&mut rl);
+        *self.rl.lock().unwrap() = Some(rl);
+        Ok(ret)
+    }
+
+    /// Ask for confirmation of a command, returns an error if it is not confirmed.
+    fn confirm_or_refuse(&self, message: &str) -> stdResult<(), XcliError> {
+        if !prompt::is_interactive() {
+            return Err(XcliError::Other(
+                "Confirmation required, use --yes in non-interactive mode".into(),
+            ));
+        }
+        match self.confirm(message)? {
+            true => Ok(()),
+            false => Err(XcliError::Other("Cancelled".into())),
+        }
+    }
+
+    /// Get the privilege level of the session, 0 by default.
+    pub fn get_privilege(&self) -> u8 {
+        *self.privilege.lock().unwrap()
+    }
+
+    /// Set the privilege level of the session, e.g. by the role of the user
+    /// logged in, without asking for a password.
+    pub fn set_privilege(&self, level: u8) {
+        *self.privilege.lock().unwrap() = level;
+    }
+
+    /// Get the command tree without the commands hidden, disabled, experimental
+    /// unless enabled, or above the privilege level of the session.
+    pub(crate) fn visible_tree(&self) -> Command<'a> {
+        self.tree().visible(self.get_privilege(), self.is_experimental())
+    }
+
+    /// Whether the experimental commands are enabled, false by default.
+    pub fn is_experimental(&self) -> bool {
+        self.tree.is_experimental()
+    }
+
+    /// Enable or disable the experimental commands, which are hidden and
+    /// can't be executed until enabled.
+    pub fn set_experimental(&self, enabled: bool) {
+        self.tree.set_experimental(enabled);
+    }
+
+    /// Record the commands executed to the audit log, whatever they end with,
+    /// e.g. denied, refused or unknown. It replaces the audit log set before.
+    pub fn audit(mut self, audit: Audit) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Set whether the history of the CLI loop is loaded from and saved to
+    /// history.txt, true by default.
+    pub fn history(mut self, enabled: bool) -> Self {
+        self.history = enabled;
+        self
+    }
+
+    /// Get the command tree, as it is now.
+    pub(crate) fn tree(&self) -> Arc<Command<'a>> {
+        self.tree.get()
+    }
+
+    /// Get a handle to the command tree, to add or remove commands while the
+    /// CLI is running, e.g. by another thread, as `App::run` takes the app.
+    pub fn command_tree(&self) -> CommandTree<'a> {
+        self.tree.clone()
+    }
+
     /// Add sub commands to command tree.
     pub fn add_subcommand(&mut self, subcmd: Command<'a>) {
-        self.tree.subcommands.push(subcmd);
+        self.tree.update(|tree| tree.subcommands.push(subcmd));
+    }
+
+    /// Add the commands defined by a type, which derives `XcliCommand`.
+    pub fn add_commands<T: XcliCommand + Execute>(&mut self) {
+        self.tree.update(|tree| tree.subcommands.extend(T::commands()));
+    }
+
+    /// Add a command to the parent command at path, `&[]` for the top level,
+    /// while the CLI is running, e.g. when a device is connected.
+    ///
+    /// It fails if the parent is not found, or the name or the alias of cmd is
+    /// taken by a subcommand of it.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// # fn cli_connect(app: &App, _args: &[&str]) -> XcliResult {
+    /// app.add_command(&["device"], Command::new("eth1").about("the device connected"))?;
+    /// # Ok(CmdExeCode::Ok)
+    /// # }
+    /// ```
+    pub fn add_command(&self, parent: &[&str], cmd: Command<'a>) -> stdResult<(), XcliError> {
+        self.tree.add_command(parent, cmd)
+    }
+
+    /// Remove the command at path, with its subcommands and its userdata,
+    /// while the CLI is running.
+    pub fn remove_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        self.tree.remove_command(path)
+    }
+
+    /// Enable the command at path, which has been disabled.
+    pub fn enable_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        self.tree.enable_command(path)
+    }
+
+    /// Disable the command at path, with its subcommands, which are hidden
+    /// from completion, help and tree, and can't be executed, until enabled again.
+    pub fn disable_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        self.tree.disable_command(path)
     }
 
     /// Add sub commands to commands, with user defined data.
@@ -213,134 +900,833 @@ impl<'a> App<'a> {
     /// userdata must be an IAny trait object, which will be associated with the
     /// command name of the subcmd.
     pub fn add_subcommand_with_userdata(&mut self, subcmd: Command<'a>, value: IAny) {
-        self.handlers.insert(subcmd.name.clone(), value);
-        self.tree.subcommands.push(subcmd);
+        self.tree.set_handler(&subcmd.name, value);
+        self.tree.update(|tree| tree.subcommands.push(subcmd));
     }
 
     /// Show all commands and their subcommands like a tree
     pub fn show_tree(&self) {
-        self.rl.borrow().helper().unwrap().print_tree("");
+        xprint!("{}", help::tree(&self.visible_tree(), &self.name, None, false));
+    }
+
+    /// Get the command tree as JSON, with names, aliases, descriptions, usage,
+    /// arguments, flags and examples of the commands visible to the session,
+    /// as `tree --json`.
+    ///
+    /// Use `serde` to serialize a `Command` in other formats.
+    pub fn tree_json(&self) -> String {
+        schema::to_json(self, &self.visible_tree())
     }
 
-    /// Get handler
-    pub fn get_handler<S: Into<String>>(&self, key: S) -> stdResult<&IAny, XcliError> {
+    /// Generate the reference document of all commands, as a man page,
+    /// Markdown or HTML.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI").version("v0.1");
+    /// std::fs::write("xcli.1", app.generate_doc(DocFormat::Man)).unwrap();
+    /// ```
+    pub fn generate_doc(&self, format: DocFormat) -> String {
+        doc::generate(self, format)
+    }
+
+    /// Get handler, i.e. the userdata of the command, which is gone once the
+    /// command is removed.
+    pub fn get_handler<S: Into<String>>(&self, key: S) -> stdResult<Arc<IAny>, XcliError> {
         let ks = key.into();
-        self.handlers.get(&ks).ok_or(XcliError::MissingHandler(ks))
+        self.tree.get_handler(&ks).ok_or(XcliError::MissingHandler(ks))
+    }
+
+    /// Get the value of a session variable
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        self.vars.lock().unwrap().get(name).cloned()
+    }
+
+    /// Set a session variable, returns the old value if any.
+    ///
+    /// The name of the variable must start with a letter or '_', followed by
+    /// letters, digits or '_'.
+    pub fn set_var<S: Into<String>>(&self, name: &str, value: S) -> stdResult<Option<String>, XcliError> {
+        if !parser::is_valid_name(name) {
+            return Err(XcliError::BadArgument(format!("{}, bad variable name", name)));
+        }
+        Ok(self.vars.lock().unwrap().insert(name.to_string(), value.into()))
+    }
+
+    /// Remove a session variable, returns its value if any.
+    pub fn unset_var(&self, name: &str) -> Option<String> {
+        self.vars.lock().unwrap().remove(name)
+    }
+
+    /// Get all session variables, sorted by name.
+    pub fn get_vars(&self) -> Vec<(String, String)> {
+        let mut vars = self
+            .vars
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        vars.sort();
+        vars
+    }
+
+    /// Get the status of the last command executed, which is referred as `$?`.
+    ///
+    /// It is "Ok" or "Exit" if the command succeeded, otherwise the kind of
+    /// the error, e.g. "BadArgument".
+    pub fn last_status(&self) -> String {
+        self.last_status.lock().unwrap().clone()
+    }
+
+    /// Save the status of the command just executed.
+    fn set_last_status(&self, ret: &XcliResult) {
+        *self.last_status.lock().unwrap() = status::describe(ret);
+    }
+
+    /// Report the error of the invocation, and record it to the audit log.
+    fn fail(&self, invocation: &Invocation, err: XcliError) -> XcliResult {
+        self.report_error(&err, Some(invocation.command));
+        let ret = Err(err);
+        self.audit_invocation(invocation, &ret);
+        ret
+    }
+
+    /// Record the invocation to the audit log, if any, with the sensitive
+    /// arguments redacted.
+    fn audit_invocation(&self, invocation: &Invocation, ret: &XcliResult) {
+        if let Some(audit) = &self.audit {
+            let args = invocation.command.redact(invocation.args);
+            audit.record(invocation.path, &args, &status::describe(ret));
+        }
+    }
+
+    /// Record the unknown command under path to the audit log, if any, with
+    /// the arguments after the unknown word redacted, as they are unknown to be
+    /// sensitive or not.
+    fn audit_unknown(&self, path: &[&str], args: &[&str]) {
+        if let Some(audit) = &self.audit {
+            let args: Vec<_> = args
+                .iter()
+                .enumerate()
+                .map(|(i, a)| if i == 0 { a.to_string() } else { audit::REDACTED.to_string() })
+                .collect();
+            audit.record(path, &args, "UnknownCommand");
+        }
+    }
+
+    /// Record the command line failed before any command is found, e.g. by
+    /// bad syntax, to the audit log, if any.
+    pub(crate) fn audit_error(&self, err: &XcliError) {
+        if let Some(audit) = &self.audit {
+            audit.record(&[], &[], err.kind());
+        }
+    }
+
+    /// Get the command line with the sensitive arguments redacted, for logs
+    /// and history, including the commands substituted by `$(...)`.
+    ///
+    /// Returns None if it can't be told, e.g. the command is given by a
+    /// variable, so that the line is kept out of logs and history.
+    pub(crate) fn redact_line(&self, line: &str) -> Option<String> {
+        let tokens = parser::tokenize(line).ok()?;
+        let tree = self.tree();
+        let mut redacted = line.to_string();
+        // the commands, split by operators, replaced from the last word, so that the spans hold
+        for command in tokens.split(|(token, _, _)| matches!(token, parser::Token::Op(_))).rev() {
+            let words: Vec<_> = command
+                .iter()
+                .filter_map(|(token, start, end)| match token {
+                    parser::Token::Word(w) => Some((w.as_str(), *start, *end)),
+                    parser::Token::Op(_) => None,
+                })
+                .skip_while(|(w, _, _)| ["if", "then", "elif", "else", "do"].contains(w))
+                .collect();
+            let literals: Vec<_> = words.iter().map(|(w, _, _)| parser::literal_word(w)).collect();
+
+            // resolved by the literal words, as the quotes are removed when executed
+            let mut cmd = tree.as_ref();
+            let mut depth = 0;
+            while let Some(literal) = literals.get(depth) {
+                let Some(literal) = literal else {
+                    // a subcommand, or even the command, is not known
+                    if depth == 0 || !cmd.subcommands.is_empty() {
+                        return None;
+                    }
+                    break;
+                };
+                match cmd.find_subcommand(literal) {
+                    Some(sub) => cmd = sub,
+                    None => break,
+                }
+                depth += 1;
+                if cmd.replaced_by.is_some() {
+                    break;
+                }
+            }
+
+            let args = &literals[depth..];
+            let values = match args.iter().map(Option::as_deref).collect::<Option<Vec<_>>>() {
+                Some(args) => cmd.redact(&args),
+                // the positions are not known before expanded
+                None if cmd.is_sensitive() => return None,
+                None => vec![],
+            };
+            for (idx, &(word, start, end)) in words.iter().enumerate().rev() {
+                let value = idx.checked_sub(depth).and_then(|i| values.get(i));
+                match (value, &literals[idx]) {
+                    (Some(value), Some(literal)) if value != literal => redacted.replace_range(start..end, value),
+                    _ => {
+                        for (from, to) in parser::substitutions(word).into_iter().rev() {
+                            let inner = self.redact_line(&word[from..to])?;
+                            redacted.replace_range(start + from..start + to, &inner);
+                        }
+                    }
+                }
+            }
+        }
+        Some(redacted)
+    }
+
+    /// Look up a variable for expansion, session variables first, then environment variables.
+    pub(crate) fn lookup_var(&self, name: &str) -> Option<String> {
+        self.get_var(name).or_else(|| std::env::var(name).ok())
+    }
+
+    /// Get the output format for structured data, which is the one given by
+    /// the `--output` option of the running command, or the session-wide one.
+    pub fn get_output_format(&self) -> OutputFormat {
+        format::current_format().unwrap_or_else(|| *self.output_format.lock().unwrap())
+    }
+
+    /// Set the session-wide output format for structured data.
+    pub fn set_output_format(&self, format: OutputFormat) {
+        *self.output_format.lock().unwrap() = format;
+    }
+
+    /// Render structured data in the output format, e.g. a `Table`, or any
+    /// value which implements `serde::Serialize`.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// # fn show(app: &App, _args: &[&str]) -> XcliResult {
+    /// let table = Table::new(vec!["port", "state"]).row(vec!["1", "up"]).row(vec!["2", "down"]);
+    /// app.render(&table)
+    /// # }
+    /// ```
+    pub fn render<T: serde::Serialize + ?Sized>(&self, value: &T) -> XcliResult {
+        let out = format::render(value, self.get_output_format())?;
+        xprint!("{}", out);
+        Ok(CmdExeCode::Ok)
+    }
+
+    /// Whether the command running on the calling thread has been asked to stop.
+    ///
+    /// Long running actions should check it periodically, so that they can be
+    /// cancelled by `kill` when running as a background job.
+    pub fn is_cancelled(&self) -> bool {
+        Job::current_cancelled()
     }
 
     /// Get the status return by args command
-    fn _run(&mut self, args: Vec<&str>) -> XcliResult {
-        self.tree.run_sub(&self, &args)
+    fn _run(&self, args: Vec<&str>) -> XcliResult {
+        self.tree().run_sub(self, &args)
     }
 
-    /// Run the instance
-    pub fn run(mut self) {
-        info!("starting CLI loop...");
+    /// Get the number of lines in a page of the pager, None if it follows the terminal height.
+    ///
+    /// `Some(0)` means paging is disabled.
+    pub fn get_terminal_length(&self) -> Option<usize> {
+        *self.terminal_length.lock().unwrap()
+    }
+
+    /// Set the number of lines in a page of the pager, None to follow the terminal height.
+    ///
+    /// `Some(0)` disables paging.
+    pub fn set_terminal_length(&self, length: Option<usize>) {
+        *self.terminal_length.lock().unwrap() = length;
+    }
+
+    /// Get the page size of the foreground output, None if it is not to be paged.
+    fn page_size(&self) -> Option<usize> {
+        if !stdout().is_terminal() {
+            return None;
+        }
+        let length = self
+            .get_terminal_length()
+            .or_else(|| crossterm::terminal::size().ok().map(|(_, h)| h as usize))?;
+        // a page needs one line for the prompt, at least
+        if length > 1 {
+            Some(length)
+        } else {
+            None
+        }
+    }
+
+    /// Execute a command line, and save its status
+    fn execute(&self, line: &str) -> XcliResult {
+        if let Some(page) = self.page_size() {
+            pager::begin(page);
+        }
+        let ret = script::run(self, line);
+        pager::end();
+        self.set_last_status(&ret);
+        self.status.lock().unwrap().record(&ret);
+        ret
+    }
+
+    /// Run a script, which may have more than one line of commands, chained by
+    /// `;`, `&&` or `||`, and `if` or `for` compound commands.
+    ///
+    /// This doesn't need the CLI loop, so it can be used to test the commands.
+    /// Note background jobs are not available then.
+    ///
+    /// The result is recorded in the exit status of the session, see `exit_status()`.
+    pub fn run_script(&self, text: &str) -> XcliResult {
+        let ret = script::run(self, text);
+        self.status.lock().unwrap().record(&ret);
+        ret
+    }
+
+    /// Get the exit status of the session, which is updated by each command
+    /// line executed, by `run()` or `run_script()`.
+    pub fn exit_status(&self) -> ExitStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Set the exit code, as given by `exit <code>`.
+    pub fn set_exit_code(&self, code: i32) {
+        self.status.lock().unwrap().exit_code = Some(code);
+    }
+
+    /// Run a command line with its output captured, for command substitution.
+    ///
+    /// Returns the output with trailing newlines removed.
+    pub(crate) fn capture_line(&self, line: &str) -> stdResult<String, XcliError> {
+        let (ret, output) = output::capture(|| script::run(self, line));
+        self.set_last_status(&ret);
+
+        match ret {
+            Ok(_) => Ok(output.trim_end_matches(&['\r', '\n'][..]).to_string()),
+            Err(_) => {
+                // show the error message of the command
+                xprint!("{}", output);
+                Err(XcliError::Other(format!("Command substitution failed: $({})", line)))
+            }
+        }
+    }
+
+    /// Run a background job on the calling thread, with its output captured.
+    fn run_job(&self, job: Arc<Job>) {
+        debug!("job [{}] started: {}", job.id, self.redact_line(&job.line).as_deref().unwrap_or(UNREDACTED));
 
-        self.rl.borrow_mut().set_completion_type(CompletionType::List);
-        self.rl.borrow_mut().set_helper(Some(PrefixCompleter::new(&self.tree)));
+        output::begin_capture(job.output.clone());
+        job.enter();
+        let ret = script::run(self, &job.line);
+        Job::leave();
+        output::end_capture();
 
-        if self.rl.borrow_mut().load_history("history.txt").is_err() {
-            println!("No previous history.");
+        debug!("job [{}] done: {:?}", job.id, ret);
+        job.finish(ret);
+    }
+
+    /// Read a command line, with continuation lines if it is incomplete, e.g.
+    /// ends with '\\', or has unterminated quotes or unbalanced brackets.
+    fn read_line(&self) -> rustyline::Result<String> {
+        // the line read from a non-tty device has the newline kept
+        fn chomp(mut line: String) -> String {
+            while line.ends_with(&['\r', '\n'][..]) {
+                line.pop();
+            }
+            line
         }
 
+        let mut line = chomp(self.edit_line("# ", "")?);
+        while parser::check_input(&line).is_incomplete() {
+            let next = chomp(self.edit_line("> ", &line)?);
+            parser::join_lines(&mut line, &next);
+        }
+        Ok(line)
+    }
+
+    /// Read a line with the editor, showing the help of the line when `?` is
+    /// pressed, with previous as the lines entered before.
+    fn edit_line(&self, prompt: &str, previous: &str) -> rustyline::Result<String> {
+        let mut initial = (String::new(), String::new());
         loop {
-            let readline = self.rl.borrow_mut().readline("# ");
-            let line = match readline {
-                Ok(line) => {
-                    self.rl.borrow_mut().add_history_entry(line.as_str());
-                    debug!("Line: {}", line);
-                    line
+            self.help_request.lock().unwrap().previous = previous.to_string();
+            let ret = self
+                .with_editor(|rl| rl.readline_with_initial(prompt, (&initial.0, &initial.1)))
+                .map_err(|err| ReadlineError::Io(std::io::Error::other(err.to_string())))?;
+            let mut request = self.help_request.lock().unwrap();
+            match (ret, request.line.take()) {
+                (Err(ReadlineError::Interrupted), Some((line, pos))) => {
+                    let tree = self.visible_tree();
+                    print!("{}", help::context_help(&tree, &request.text(&line[..pos])));
+                    initial = (line[..pos].to_string(), line[pos..].to_string());
                 }
-                Err(err) => {
-                    println!("Error: {:?}", err);
-                    let l = self
-                        .rl
-                        .borrow_mut()
-                        .readline("Do you realy want to quit? [y/N]")
-                        .unwrap_or_else(|_| "n".parse().unwrap());
-                    match l.as_str() {
-                        "Y" | "y" => break,
-                        _ => "".to_string(),
-                    }
+                (ret, _) => return ret,
+            }
+        }
+    }
+
+    /// Run the instance, until `exit` or the end of input.
+    ///
+    /// Background jobs still running are asked to stop then. If they don't
+    /// stop in 5 seconds, the process exits without waiting for them.
+    ///
+    /// Returns the exit status of the session, which a `main` can exit with,
+    /// e.g. `std::process::exit(app.run().code())`.
+    pub fn run(self) -> ExitStatus {
+        info!("starting CLI loop...");
+
+        if let Some(rl) = self.rl.lock().unwrap().as_mut() {
+            rl.set_completion_type(CompletionType::List);
+            rl.set_helper(Some(PrefixCompleter {
+                tree: self.tree.completion.clone(),
+                privilege: self.privilege.clone(),
+                asking: None,
+            }));
+            rl.bind_sequence(
+                KeyEvent::from('?'),
+                EventHandler::Conditional(Box::new(help::HelpKey(self.help_request.clone()))),
+            );
+
+            if self.history && rl.load_history("history.txt").is_err() {
+                println!("No previous history.");
+            }
+        }
+
+        thread::scope(|s| {
+            // background jobs are spawned in this scope, so that they can borrow the app
+            let (tx, rx) = mpsc::channel::<Arc<Job>>();
+            self.jobs.set_spawner(Some(tx));
+            let app = &self;
+            s.spawn(move || {
+                for job in rx {
+                    s.spawn(move || app.run_job(job));
                 }
-            };
+            });
 
-            let args = line.split_ascii_whitespace().collect::<Vec<_>>();
+            loop {
+                for job in self.jobs.take_finished() {
+                    println!("[{}] {:12} {}", job.id, job.status(), job.line);
+                }
 
-            // skip empty input line
-            if !args.is_empty() {
-                // for no any args, do nothing but to continue loop
-                if let Ok(CmdExeCode::Exit) = self._run(args) {
+                let readline = self.read_line();
+                let line = match readline {
+                    Ok(line) => {
+                        // lines with sensitive arguments, e.g. passwords, are not kept in history
+                        let redacted = self.redact_line(&line);
+                        if redacted.as_ref() == Some(&line) {
+                            if let Some(rl) = self.rl.lock().unwrap().as_mut() {
+                                rl.add_history_entry(line.as_str());
+                            }
+                        }
+                        debug!("Line: {}", redacted.as_deref().unwrap_or(UNREDACTED));
+                        line
+                    }
+                    Err(ReadlineError::Eof) => break,
+                    Err(err) => {
+                        println!("Error: {:?}", err);
+                        match self.confirm("Do you really want to quit?") {
+                            Ok(true) => break,
+                            _ => "".to_string(),
+                        }
+                    }
+                };
+
+                if let Ok(CmdExeCode::Exit) = self.execute(&line) {
                     break;
                 }
             }
+
+            // stop the job spawner, and the jobs still running
+            self.jobs.set_spawner(None);
+            self.jobs.cancel_all();
+            let running = self.jobs.all().into_iter().filter(|j| !j.is_done()).collect::<Vec<_>>();
+            if !running.is_empty() {
+                println!("Waiting for {} background job(s) to stop...", running.len());
+            }
+            let deadline = Instant::now() + JOB_STOP_TIMEOUT;
+            let stuck = running
+                .iter()
+                .filter(|j| !j.wait_timeout(deadline.saturating_duration_since(Instant::now())))
+                .count();
+            if stuck > 0 {
+                // the scope can't be left with the jobs running, as they borrow the app
+                println!("{} background job(s) didn't stop in {:?}, exiting anyway", stuck, JOB_STOP_TIMEOUT);
+                self.save_history();
+                std::process::exit(self.exit_status().code());
+            }
+        });
+
+        self.save_history();
+        self.exit_status()
+    }
+
+    /// Save the history of the CLI loop to history.txt, if enabled.
+    fn save_history(&self) {
+        if let Some(rl) = self.rl.lock().unwrap().as_mut().filter(|_| self.history) {
+            rl.save_history("history.txt").unwrap();
+        }
+    }
+
+    /// Run the instance by the arguments of the program, e.g. `std::env::args()`,
+    /// the first of which is the program name.
+    ///
+    /// The arguments are executed as a single command, or the CLI loop is run
+    /// if there is none. Global options can be given before the command:
+    ///
+    /// * `--script <file>`, executes the commands in the file.
+    /// * `--command <line>`, executes the command line, which may have
+    ///   variables, `;`, `&&`, `||` etc.
+    /// * `--no-history`, doesn't load or save the history of the CLI loop.
+    /// * `--help` or `-h`, shows the global options and the commands, then exits.
+    /// * `--version`, shows the name and version, then exits.
+    ///
+    /// ```no_run
+    /// # use xcli::*;
+    /// let app = App::new("xCLI");
+    /// // e.g. 'xcli terminal length 0', or 'xcli --command "set n 3; echo $n"'
+    /// std::process::exit(app.run_from_args(std::env::args()).code());
+    /// ```
+    pub fn run_from_args<I, S>(mut self, args: I) -> ExitStatus
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        enum Task<'s> {
+            Script(&'s str),
+            Line(&'s str),
+            Command(Vec<&'s str>),
         }
-        self.rl.borrow_mut().save_history("history.txt").unwrap();
+
+        let mut args = args.into_iter().map(Into::into);
+        let program = args.next().unwrap_or_default();
+        let args: Vec<String> = args.collect();
+        let mut tasks = vec![];
+        let mut iter = args.iter().map(String::as_str);
+        while let Some(arg) = iter.next() {
+            let task = match arg {
+                "--no-history" => {
+                    self.history = false;
+                    continue;
+                }
+                // handled before anything is executed
+                "--help" | "-h" => {
+                    let program = std::path::Path::new(&program).file_name().and_then(|p| p.to_str());
+                    xprint!("{}", help::program_help(&self.visible_tree(), program.unwrap_or(&self.name)));
+                    return self.exit_status();
+                }
+                "--version" => {
+                    xprintln!("{} {}", self.name, self.get_version());
+                    return self.exit_status();
+                }
+                "--script" | "--command" => {
+                    let Some(value) = iter.next() else {
+                        let err = XcliError::RequiredArgument(format!("{} <value>", arg));
+                        return self.fail_with(err);
+                    };
+                    if arg == "--script" {
+                        Task::Script(value)
+                    } else {
+                        Task::Line(value)
+                    }
+                }
+                "--" => Task::Command(iter.by_ref().collect()),
+                _ if arg.starts_with("--") => {
+                    return self.fail_with(XcliError::BadArgument(format!("{}, unknown option", arg)));
+                }
+                _ => Task::Command(std::iter::once(arg).chain(iter.by_ref()).collect()),
+            };
+            tasks.push(task);
+        }
+
+        if tasks.is_empty() {
+            return self.run();
+        }
+        for task in tasks {
+            let ret = match task {
+                Task::Script(file) => match read_script(file) {
+                    Ok(text) => script::run(&self, &text),
+                    Err(err) => {
+                        self.report_error(&err, None);
+                        Err(err)
+                    }
+                },
+                Task::Line(line) => script::run(&self, line),
+                Task::Command(args) if args.is_empty() => continue,
+                Task::Command(args) => self._run(args),
+            };
+            self.status.lock().unwrap().record(&ret);
+            if let Ok(CmdExeCode::Exit) = ret {
+                break;
+            }
+        }
+        self.exit_status()
+    }
+
+    /// Report an error which fails the run, and get the exit status.
+    fn fail_with(&self, err: XcliError) -> ExitStatus {
+        self.report_error(&err, None);
+        let ret = Err(err);
+        self.status.lock().unwrap().record(&ret);
+        self.exit_status()
+    }
+}
+
+impl<'a> Command<'a> {
+    /// Create a command
+    pub fn new<S: Into<String>>(n: S) -> Self {
+        Command {
+            name: n.into(),
+            alias: None,
+            about: None,
+            usage: None,
+            long_about: None,
+            args: vec![],
+            flags: vec![],
+            examples: vec![],
+            see_also: vec![],
+            sensitive_args: vec![],
+            sensitive_flags: vec![],
+            privilege: 0,
+            confirm: None,
+            output_option: false,
+            disabled: false,
+            hidden: false,
+            deprecated: None,
+            replaced_by: None,
+            experimental: false,
+            subcommands: vec![],
+            action: None,
+        }
+    }
+
+    pub fn new_with_alias<S: Into<String>>(n: S, s: S) -> Self {
+        Command {
+            name: n.into(),
+            alias: Some(s.into()),
+            about: None,
+            usage: None,
+            long_about: None,
+            args: vec![],
+            flags: vec![],
+            examples: vec![],
+            see_also: vec![],
+            sensitive_args: vec![],
+            sensitive_flags: vec![],
+            privilege: 0,
+            confirm: None,
+            output_option: false,
+            disabled: false,
+            hidden: false,
+            deprecated: None,
+            replaced_by: None,
+            experimental: false,
+            subcommands: vec![],
+            action: None,
+        }
+    }
+
+    /// Get the name of this command.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the alias name of this command.
+    pub fn get_alias(&self) -> &Option<String> {
+        &self.alias
+    }
+
+    // Get the name description, aka. name+alias of this command.
+    pub(crate) fn get_description(&self) -> String {
+        let mut s = self.name.clone();
+        if let Some(alias) = self.alias.as_ref() {
+            s = format!("{}, {} ", s, alias);
+        }
+        s
+    }
+
+    /// Set a CmdAction to this command.
+    pub fn action(mut self, action: CmdAction) -> Self {
+        self.action = Some(action);
+        self
+    }
+
+    /// Set a description to this command.
+    pub fn about<S: Into<&'a str>>(mut self, about: S) -> Self {
+        self.about = Some(about.into());
+        self
+    }
+
+    /// Set the usage to this command.
+    pub fn usage<S: Into<&'a str>>(mut self, about: S) -> Self {
+        self.usage = Some(about.into());
+        self
+    }
+
+    /// Set a long description to this command, shown by `help <command>`.
+    ///
+    /// Newlines start new paragraphs, otherwise the text is wrapped to the width of terminal.
+    pub fn long_about<S: Into<&'a str>>(mut self, long_about: S) -> Self {
+        self.long_about = Some(long_about.into());
+        self
+    }
+
+    /// Document an argument of this command, e.g. `.arg("<file>", "the script file")`.
+    pub fn arg<S: Into<&'a str>>(mut self, name: S, help: S) -> Self {
+        self.args.push((name.into(), help.into()));
+        self
+    }
+
+    /// Document a flag of this command, e.g. `.flag("-n <seconds>", "interval between runs")`.
+    pub fn flag<S: Into<&'a str>>(mut self, flag: S, help: S) -> Self {
+        self.flags.push((flag.into(), help.into()));
+        self
+    }
+
+    /// Add an example to this command, with the command line and what it does.
+    pub fn example<S: Into<&'a str>>(mut self, example: S, help: S) -> Self {
+        self.examples.push((example.into(), help.into()));
+        self
+    }
+
+    /// Refer to a related command, e.g. `.see_also("jobs")`.
+    pub fn see_also<S: Into<&'a str>>(mut self, command: S) -> Self {
+        self.see_also.push(command.into());
+        self
+    }
+
+    /// Mark the argument at idx as sensitive, e.g. a password, which is
+    /// redacted in the audit log and the debug logs, and keeps the command
+    /// line out of history.
+    pub fn sensitive_arg(mut self, idx: usize) -> Self {
+        self.sensitive_args.push(idx);
+        self
+    }
+
+    /// Mark the value of a flag as sensitive, e.g. `.sensitive_flag("--password")`,
+    /// which is redacted as the sensitive arguments, given as `--password <value>` or `--password=<value>`.
+    pub fn sensitive_flag<S: Into<&'a str>>(mut self, flag: S) -> Self {
+        self.sensitive_flags.push(flag.into());
+        self
     }
-}
 
-impl<'a> Command<'a> {
-    /// Create a command
-    pub fn new<S: Into<String>>(n: S) -> Self {
-        Command {
-            name: n.into(),
-            alias: None,
-            about: None,
-            usage: None,
-            subcommands: vec![],
-            action: None,
-        }
+    /// Whether any argument of this command is sensitive.
+    pub(crate) fn is_sensitive(&self) -> bool {
+        !self.sensitive_args.is_empty() || !self.sensitive_flags.is_empty()
     }
 
-    pub fn new_with_alias<S: Into<String>>(n: S, s: S) -> Self {
-        Command {
-            name: n.into(),
-            alias: Some(s.into()),
-            about: None,
-            usage: None,
-            subcommands: vec![],
-            action: None,
+    /// Get the arguments with the sensitive ones redacted.
+    pub(crate) fn redact(&self, args: &[&str]) -> Vec<String> {
+        let mut redacted = vec![];
+        let mut after_flag = false;
+        for (i, &arg) in args.iter().enumerate() {
+            let sensitive = after_flag || self.sensitive_args.contains(&i);
+            after_flag = self.sensitive_flags.contains(&arg);
+            let arg = match arg.split_once('=') {
+                _ if sensitive => audit::REDACTED.to_string(),
+                Some((flag, _)) if self.sensitive_flags.contains(&flag) => format!("{}={}", flag, audit::REDACTED),
+                _ => arg.to_string(),
+            };
+            redacted.push(arg);
         }
+        redacted
     }
 
-    /// Get the name of this command.
-    pub fn get_name(&self) -> &str {
-        &self.name
+    /// Set the privilege level required to run this command and its
+    /// subcommands, 0 by default.
+    ///
+    /// The command is hidden from completion, help and tree if the session
+    /// is not at the level, see `App::set_privilege` and the `enable` builtin.
+    pub fn privilege(mut self, level: u8) -> Self {
+        self.privilege = level;
+        self
     }
 
-    /// Get the alias name of this command.
-    pub fn get_alias(&self) -> &Option<String> {
-        &self.alias
+    /// Ask for confirmation before running this command, with the message,
+    /// e.g. `.confirm("This will erase all data, continue?")`.
+    ///
+    /// `--yes` or `-y` skips it, which is required in non-interactive mode,
+    /// otherwise the command is refused.
+    pub fn confirm<S: Into<&'a str>>(mut self, message: S) -> Self {
+        self.confirm = Some(message.into());
+        self.push_flag(("-y, --yes", "runs without asking for confirmation"));
+        self
     }
 
-    // Get the name description, aka. name+alias of this command.
-    fn get_description(&self) -> String {
-        let mut s = self.name.clone();
-        if let Some(alias) = self.alias.as_ref() {
-            s = format!("{}, {} ", s, alias);
+    /// Accept the `--output <format>` option, which overrides the output
+    /// format of `App::render` for this run, and is removed from the arguments.
+    ///
+    /// Options after `--` are left to the action.
+    pub fn output_option(mut self) -> Self {
+        self.output_option = true;
+        self.push_flag(("--output <format>", "the output format, table, json, yaml or csv"));
+        self
+    }
+
+    /// Add the flag added by an attribute, unless it is there, as the
+    /// attribute may be set more than once.
+    fn push_flag(&mut self, flag: (&'a str, &'a str)) {
+        if !self.flags.iter().any(|(f, _)| *f == flag.0) {
+            self.flags.push(flag);
         }
-        s
     }
 
-    /// Set a CmdAction to this command.
-    pub fn action(mut self, action: CmdAction) -> Self {
-        self.action = Some(action);
+    /// Hide this command and its subcommands from completion, help and tree,
+    /// which still can be executed.
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
         self
     }
 
-    /// Set a description to this command.
-    pub fn about<S: Into<&'a str>>(mut self, about: S) -> Self {
-        self.about = Some(about.into());
+    /// Mark this command as deprecated, e.g. `.deprecated("use 'port show' instead")`,
+    /// which is printed to stderr as a warning when it is used.
+    pub fn deprecated<S: Into<&'a str>>(mut self, message: S) -> Self {
+        self.deprecated = Some(message.into());
         self
     }
 
-    /// Set the usage to this command.
-    pub fn usage<S: Into<&'a str>>(mut self, about: S) -> Self {
-        self.usage = Some(about.into());
+    /// Set the command which replaces this deprecated one, e.g. `.replaced_by("port show")`,
+    /// which is executed instead, with the arguments given.
+    pub fn replaced_by<S: Into<&'a str>>(mut self, command: S) -> Self {
+        self.replaced_by = Some(command.into());
+        self
+    }
+
+    /// Mark this command as experimental, which is hidden and can't be
+    /// executed, until enabled by `App::set_experimental`.
+    pub fn experimental(mut self) -> Self {
+        self.experimental = true;
         self
     }
 
+    /// Get the privilege level required to run this command.
+    pub fn get_privilege(&self) -> u8 {
+        self.privilege
+    }
+
+    /// Whether this command is shown in completion, help and tree, i.e. not
+    /// hidden, disabled, or experimental unless enabled.
+    pub(crate) fn is_shown(&self, experimental: bool) -> bool {
+        !self.hidden && !self.disabled && (experimental || !self.experimental)
+    }
+
+    /// Get a copy of this command, with the subcommands shown at the privilege level only.
+    pub(crate) fn visible(&self, level: u8, experimental: bool) -> Command<'a> {
+        Command {
+            subcommands: self
+                .subcommands
+                .iter()
+                .filter(|c| c.is_shown(experimental) && c.privilege <= level)
+                .map(|c| c.visible(level, experimental))
+                .collect(),
+            ..self.clone()
+        }
+    }
+
     /// Get all subcommands of this command
     pub fn get_subcommands(&self) -> &[Command<'a>] {
         &self.subcommands
@@ -365,34 +1751,46 @@ impl<'a> Command<'a> {
 
     /// show usage message for command.
     pub fn show_command_usage(&self) {
-        println!("Usage:       {}", self.usage.unwrap_or_else(|| self.name.as_ref()));
+        xprintln!("Usage:       {}", self.usage.unwrap_or_else(|| self.name.as_ref()));
     }
 
-    /// show help message for command.
+    /// show help message for command, with its long description, arguments,
+    /// flags, examples and related commands.
     pub fn show_command_help(&self) {
-        println!(
-            "Command         : {}\nUsage           : {}\nDescription     : {}",
-            self.get_description(),
-            self.usage.unwrap_or_else(|| self.name.as_ref()),
-            self.about.unwrap_or("")
-        );
+        xprint!("{}", help::command_help(self, &self.name));
     }
 
-    /// show help message for command and its subs
+    /// show help message for command and its subs, without the hidden or
+    /// experimental ones
     pub fn show_subcommand_help(&self) {
-        println!("SubCommands");
-        for cmd in &self.subcommands {
-            println!("  {:16}: {}", cmd.get_description(), cmd.about.unwrap_or_else(|| cmd.name.as_ref()))
-        }
+        self.print_subcommand_help(false);
+    }
+
+    /// show help message for command, and its subs if any, without the
+    /// hidden or experimental ones
+    pub fn show_help(&self) {
+        self.print_help(false);
+    }
+
+    /// Show help message for command and its subs, with the experimental
+    /// ones if they are enabled.
+    fn print_subcommand_help(&self, experimental: bool) {
+        xprint!("{}", help::subcommand_help(&self.visible(u8::MAX, experimental)));
+    }
+
+    /// Show help message for command, and its subs if any, with the
+    /// experimental ones if they are enabled.
+    fn print_help(&self, experimental: bool) {
+        xprint!("{}", help::full_help(&self.visible(u8::MAX, experimental), &self.name));
     }
 
     /// locate the sub command by the args given
-    pub fn locate_subcommand(&self, args: &[&str]) -> Option<&Command> {
+    pub fn locate_subcommand(&self, args: &[&str]) -> Option<&Command<'a>> {
         if !args.is_empty() {
             if let Some(found) = self
                 .subcommands
                 .iter()
-                .find(|&c| c.name == args[0] || c.alias.as_ref().map_or(false, |a| a == args[0]))
+                .find(|&c| c.name == args[0] || c.alias.as_ref().is_some_and(|a| a == args[0]))
             {
                 found.locate_subcommand(args[1..].to_vec().as_ref())
             } else {
@@ -405,59 +1803,194 @@ impl<'a> Command<'a> {
 
     /// run sub commands
     ///
-    /// show error message when action not found but with extra args
+    /// fail with `XcliError::UnknownCommand` when action not found but with extra args
     ///
     /// show help messages when action not found for the sub command
     ///
     /// execute sub command when action found
     ///
+    /// Every command is recorded to the audit log here, whatever it ends with,
+    /// except the deprecated ones redirected, which are recorded as the
+    /// commands they are redirected to, after they are checked.
     pub fn run_sub(&self, app: &App, args: &[&str]) -> XcliResult {
-        if !args.is_empty() {
-            for cmd in &self.subcommands {
-                if args[0] == cmd.name || cmd.alias.as_ref().map_or(false, |a| a == args[0]) {
-                    return cmd.run_sub(app, args[1..].to_vec().as_ref());
-                }
+        let chain = self.resolve(args);
+        let path: Vec<&str> = chain.iter().map(|c| c.name.as_str()).collect();
+        let cmd = chain.last().copied().unwrap_or(self);
+        let args = &args[chain.len()..];
+
+        if let Some(command) = cmd.replaced_by {
+            let invocation = Invocation {
+                command: cmd,
+                path: &path,
+                args,
+            };
+            if let Err(err) = Self::check_path(app, &chain, &path) {
+                return app.fail(&invocation, err);
+            }
+            let redirects = REDIRECTS.with(Cell::get);
+            if redirects >= MAX_REDIRECTS {
+                let err = XcliError::Other(format!(
+                    "'{}' is redirected too many times, {} at most",
+                    path.join(" "),
+                    MAX_REDIRECTS
+                ));
+                return app.fail(&invocation, err);
+            }
+            Self::warn_deprecated(&chain, &path);
+            let args = command.split_whitespace().chain(args.iter().copied()).collect();
+            REDIRECTS.with(|r| r.set(redirects + 1));
+            let ret = app._run(args);
+            REDIRECTS.with(|r| r.set(redirects));
+            return ret;
+        }
+
+        cmd.dispatch(app, &chain, &path, args)
+    }
+
+    /// Warn about the deprecated commands in chain, whose names are path.
+    fn warn_deprecated(chain: &[&Self], path: &[&str]) {
+        for (depth, cmd) in chain.iter().enumerate() {
+            if let Some(message) = cmd.deprecated {
+                eprintln!("Warning: '{}' is deprecated, {}", path[..=depth].join(" "), message);
+            }
+        }
+    }
+
+    /// Check the commands in chain, whose names are path, can be executed,
+    /// i.e. not disabled, experimental unless enabled, nor above the privilege
+    /// level of the session.
+    fn check_path(app: &App, chain: &[&Self], path: &[&str]) -> Result<(), XcliError> {
+        for (depth, cmd) in chain.iter().enumerate() {
+            if cmd.disabled {
+                return Err(XcliError::Other(format!("'{}' is disabled", path[..=depth].join(" "))));
+            }
+            if cmd.experimental && !app.is_experimental() {
+                return Err(XcliError::Other(format!(
+                    "'{}' is experimental, enable experimental commands first",
+                    path[..=depth].join(" ")
+                )));
+            }
+            if cmd.privilege > app.get_privilege() {
+                return Err(XcliError::PermissionDenied(format!(
+                    "'{}' requires privilege level {}",
+                    path[..=depth].join(" "),
+                    cmd.privilege
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the subcommand named or aliased as word.
+    fn find_subcommand(&self, word: &str) -> Option<&Self> {
+        self.subcommands
+            .iter()
+            .find(|c| c.name == word || c.alias.as_ref().is_some_and(|a| a == word))
+    }
+
+    /// Get the subcommands given by the leading words, from the top level one,
+    /// till the one redirected by `replaced_by`.
+    fn resolve(&self, words: &[&str]) -> Vec<&Self> {
+        let mut chain: Vec<&Self> = vec![];
+        let mut cmd = self;
+        while let Some(sub) = words.get(chain.len()).and_then(|word| cmd.find_subcommand(word)) {
+            chain.push(sub);
+            cmd = sub;
+            if sub.replaced_by.is_some() {
+                break;
+            }
+        }
+        chain
+    }
+
+    /// Show the help, execute the action, or complain about the unknown
+    /// arguments, of this command at path, and record it to the audit log.
+    ///
+    /// The commands in chain are checked, and the confirmation is asked, in
+    /// the middleware chain for an action, so that the hooks see it disabled,
+    /// denied or refused.
+    fn dispatch(&self, app: &App, chain: &[&Self], path: &[&str], args: &[&str]) -> XcliResult {
+        // '<command> --help', or '<command> ?' if '?' can't be an argument, e.g. not 'echo ?'
+        let help = match args {
+            ["--help"] => true,
+            ["?"] => self.action.is_none(),
+            _ => false,
+        };
+        let invocation = Invocation {
+            command: self,
+            path,
+            args,
+        };
+        if help || self.action.is_none() {
+            if let Err(err) = Self::check_path(app, chain, path) {
+                return app.fail(&invocation, err);
             }
+            Self::warn_deprecated(chain, path);
+        }
+        if help {
+            self.print_help(app.is_experimental());
+            let ret = Ok(CmdExeCode::Ok);
+            app.audit_invocation(&invocation, &ret);
+            return ret;
         }
 
         // hit an action
         if let Some(action) = &self.action {
-            debug!("action for {}, arg={:?}", self.name, args);
-            let ret = action(app, &args);
-            match &ret {
-                Err(XcliError::Other(err)) => {
-                    println!("{}", err);
-                }
-                Err(err) => {
-                    println!("{}\n", err);
-                    self.show_command_usage();
+            let (args, format) = match self.output_option {
+                true => format::take_output_option(args),
+                false => (args.to_vec(), None),
+            };
+            let (args, yes) = match self.confirm {
+                Some(_) => prompt::take_yes_option(args),
+                None => (args, true),
+            };
+            debug!("action for {}, arg={:?}", self.name, self.redact(&args));
+            let invocation = Invocation {
+                command: self,
+                path,
+                args: &args,
+            };
+            let ret = app.middleware.run(app, &invocation, &|| {
+                Self::check_path(app, chain, path)?;
+                Self::warn_deprecated(chain, path);
+                if let (Some(message), false) = (self.confirm, yes) {
+                    app.confirm_or_refuse(message)?;
                 }
-                Ok(_) => {}
+                format::with_format(format, || action(app, &args))
+            });
+            // the errors of sourced scripts are reported by the commands failed
+            let reported = script::take_reported();
+            if let (Err(err), false) = (&ret, reported) {
+                app.report_error(err, Some(self));
             }
+            app.audit_invocation(&invocation, &ret);
 
-            return ret;
+            ret
         } else {
-            // no action defined, print error message if there are some unrecognized args
+            // no action defined, fail if there are some unrecognized args
             // otherwise, show help message for this command
             if !args.is_empty() {
-                debug!("command without action, but with some args {:?}", args);
-                println!("Unknown command or arguments : {:?}", args)
-            } else {
-                debug!("command with no action defined");
-                self.show_command_help();
-                self.show_subcommand_help();
+                debug!("command without action, but with some args");
+                let err = XcliError::UnknownCommand(args.join(" "));
+                // the usage of the parent command, if any
+                app.report_error(&err, Some(self).filter(|_| !path.is_empty()));
+                app.audit_unknown(path, args);
+                return Err(err);
             }
+            debug!("command with no action defined");
+            self.print_help(app.is_experimental());
+            let ret = Ok(CmdExeCode::Ok);
+            app.audit_invocation(&invocation, &ret);
+            ret
         }
-
-        Ok(CmdExeCode::Ok)
     }
 
-    ///
-    pub fn for_each<F>(&self, path: &str, f: &mut F)
+    /// Visit this command and all its subcommands recursively, with their paths.
+    pub fn for_each<'c, F>(&'c self, path: &str, f: &mut F)
     where
-        F: FnMut(&Self, &str),
+        F: FnMut(&'c Self, &str),
     {
-        f(&self, path);
+        f(self, path);
         for a in self.get_subcommands() {
             a.for_each(format!("{}/{}", path, a.name).as_str(), f);
         }
@@ -465,14 +1998,21 @@ impl<'a> Command<'a> {
 }
 
 /// A `PrefixCompleter` for subcommands
-#[derive(Helper, Hinter, Validator, Highlighter)]
+#[derive(Helper, Hinter, Highlighter)]
 pub struct PrefixCompleter {
-    tree: PrefixNode,
+    tree: Arc<Mutex<PrefixNode>>,
+    privilege: Arc<Mutex<u8>>,
+    /// The options to complete when an action is asking the user for input,
+    /// instead of commands.
+    asking: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PrefixNode {
     name: String,
+    privilege: u8,
+    /// The flags and options of the command, e.g. "--verbose".
+    flags: Vec<String>,
     children: Vec<PrefixNode>,
 }
 
@@ -483,6 +2023,15 @@ impl PrefixNode {
         PrefixNode {
             // append a space to the cmd name
             name: cmd.name.clone().add(" "),
+            privilege: cmd.privilege,
+            // e.g. "-y, --yes" or "--count <count>"
+            flags: cmd
+                .flags
+                .iter()
+                .flat_map(|(name, _)| name.split([',', ' ']))
+                .filter(|f| f.starts_with('-'))
+                .map(|f| f.to_string().add(" "))
+                .collect(),
             children: vec![],
         }
     }
@@ -496,20 +2045,29 @@ impl PrefixNode {
 impl PrefixCompleter {
     /// Constructor, take the command tree as input
     pub fn new(cmd_tree: &Command) -> Self {
-        let mut prefix_tree = PrefixNode::new(cmd_tree);
-        for cmd in &cmd_tree.subcommands {
-            PrefixCompleter::generate_cmd_tree(&mut prefix_tree, cmd);
+        Self {
+            tree: Arc::new(Mutex::new(PrefixCompleter::build(cmd_tree, false))),
+            privilege: Arc::default(),
+            asking: None,
         }
+    }
 
-        Self { tree: prefix_tree }
+    /// Build the prefix tree of the command tree, without the commands not shown,
+    /// e.g. the hidden ones, or the experimental ones unless enabled
+    fn build(cmd_tree: &Command, experimental: bool) -> PrefixNode {
+        let mut prefix_tree = PrefixNode::new(cmd_tree);
+        for cmd in cmd_tree.subcommands.iter().filter(|c| c.is_shown(experimental)) {
+            PrefixCompleter::generate_cmd_tree(&mut prefix_tree, cmd, experimental);
+        }
+        prefix_tree
     }
 
     /// Generate the command tree by cmd and parent
-    fn generate_cmd_tree(parent: &mut PrefixNode, cmd: &Command) {
+    fn generate_cmd_tree(parent: &mut PrefixNode, cmd: &Command, experimental: bool) {
         let mut node = PrefixNode::new(cmd);
 
-        for cmd in &cmd.subcommands {
-            PrefixCompleter::generate_cmd_tree(&mut node, cmd);
+        for cmd in cmd.subcommands.iter().filter(|c| c.is_shown(experimental)) {
+            PrefixCompleter::generate_cmd_tree(&mut node, cmd, experimental);
         }
 
         debug!("prefix {} added", node.name);
@@ -521,12 +2079,13 @@ impl PrefixCompleter {
     /// partial path to be completed.
     pub fn complete_cmd(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<String>)> {
         debug!("line={} pos={}", line, pos);
-        let v = PrefixCompleter::_complete_cmd(&self.tree, line, pos);
+        let level = *self.privilege.lock().unwrap();
+        let v = PrefixCompleter::_complete_cmd(&self.tree.lock().unwrap(), line, pos, level);
         Ok((pos, v))
     }
 
-    /// Get all commands that match the line and pos
-    pub fn _complete_cmd(node: &PrefixNode, line: &str, pos: usize) -> Vec<String> {
+    /// Get all commands that match the line and pos, up to the privilege level
+    pub fn _complete_cmd(node: &PrefixNode, line: &str, pos: usize, level: u8) -> Vec<String> {
         debug!("cli to complete {} for node {}", line, node.name);
         let line = line[..pos].trim_start();
         let mut go_next = false;
@@ -536,7 +2095,7 @@ impl PrefixCompleter {
         let mut next_node = None;
 
         //var lineCompleter PrefixCompleterInterface
-        for child in &node.children {
+        for child in node.children.iter().filter(|c| c.privilege <= level) {
             //debug!("try node {}", child.name);
             if line.len() >= child.name.len() {
                 if line.starts_with(&child.name) {
@@ -559,6 +2118,19 @@ impl PrefixCompleter {
             }
         }
 
+        // no subcommand matches, complete the flags of the command
+        if new_line.is_empty() && !node.flags.is_empty() {
+            let word = line.rsplit(char::is_whitespace).next().unwrap_or("");
+            if word.is_empty() || word.starts_with('-') {
+                return node
+                    .flags
+                    .iter()
+                    .filter(|f| f.starts_with(word) && !line.split_whitespace().any(|w| w == f.trim_end()))
+                    .map(|f| f[word.len()..].to_string())
+                    .collect();
+            }
+        }
+
         // more than 1 candidates?
         if new_line.len() != 1 {
             debug!("offset={}, candidates={:?}", offset, new_line);
@@ -567,38 +2139,27 @@ impl PrefixCompleter {
 
         if go_next {
             let line = line[offset..].trim_start();
-            return PrefixCompleter::_complete_cmd(next_node.unwrap(), line, line.len());
+            return PrefixCompleter::_complete_cmd(next_node.unwrap(), line, line.len(), level);
         }
 
         debug!("offset={}, nl={:?}", offset, new_line);
         new_line
     }
+}
 
-    /// Print the command tree
-    fn print_tree(&self, prefix: &str) {
-        let s: Vec<u8> = vec![];
-        let mut writer = BufWriter::new(s);
-        let _ = PrefixCompleter::_print_tree(&self.tree, prefix, 0, &mut writer);
-        println!("{}", String::from_utf8_lossy(writer.buffer()));
-    }
-
-    /// Build the command tree recursively
-    fn _print_tree(node: &PrefixNode, prefix: &str, level: u32, buf: &mut BufWriter<Vec<u8>>) -> std::io::Result<()> {
-        let mut level = level;
-        if !node.name.is_empty() {
-            write!(buf, "{}", prefix)?;
-            if level > 0 {
-                write!(buf, "├{}", "─".repeat((level as usize * 4) - 2))?;
-            }
-            writeln!(buf, "{}", node.name)?;
-            level += 1;
+/// Reject the input with unbalanced brackets.
+///
+/// Incomplete input is accepted here, and the CLI loop reads the rest lines
+/// with a continuation prompt.
+impl Validator for PrefixCompleter {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if self.asking.is_some() {
+            return Ok(ValidationResult::Valid(None));
         }
-
-        for child in &node.children {
-            let _ = PrefixCompleter::_print_tree(child, prefix, level, buf);
+        match parser::check_input(ctx.input()) {
+            Completeness::Invalid(msg) => Ok(ValidationResult::Invalid(Some(format!("  <- {}", msg)))),
+            _ => Ok(ValidationResult::Valid(None)),
         }
-
-        Ok(())
     }
 }
 
@@ -607,18 +2168,25 @@ impl Completer for PrefixCompleter {
 
     /// Complete command
     fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        if let Some(options) = &self.asking {
+            let candidates = options.iter().filter(|o| o.starts_with(&line[..pos])).cloned().collect();
+            return Ok((0, candidates));
+        }
         self.complete_cmd(line, pos)
     }
 }
 
 /// Action of help command
 fn cli_help(app: &App, args: &[&str]) -> XcliResult {
+    let tree = app.visible_tree();
     if args.is_empty() {
-        app.tree.show_subcommand_help();
-    } else if let Some(cmd) = app.tree.locate_subcommand(args) {
-        cmd.show_command_help();
+        tree.print_subcommand_help(app.is_experimental());
+    } else if let ["-a"] = args {
+        xprint!("{}", help::reference(&tree));
+    } else if let Some(cmd) = tree.locate_subcommand(args) {
+        cmd.print_help(app.is_experimental());
     } else {
-        println!("Unrecognized command {:?}", args)
+        xprintln!("Unrecognized command {:?}", args)
     }
     Ok(CmdExeCode::Ok)
 }
@@ -627,7 +2195,7 @@ fn cli_help(app: &App, args: &[&str]) -> XcliResult {
 fn cli_log(_app: &App, args: &[&str]) -> XcliResult {
     match args.len() {
         0 => {
-            println!("Global log level is: {}", log::max_level().to_string());
+            xprintln!("Global log level is: {}", log::max_level());
         }
         1 => match args[0].parse::<LevelFilter>() {
             Ok(level) => log::set_max_level(level),
@@ -646,13 +2214,14 @@ fn cli_log(_app: &App, args: &[&str]) -> XcliResult {
 fn cli_mode(app: &App, args: &[&str]) -> XcliResult {
     match args.len() {
         0 => {
-            let mode = app.rl.borrow_mut().config_mut().edit_mode();
+            let mode = *app.edit_mode.lock().unwrap();
             let mode_str = if mode == EditMode::Vi { "Vi" } else { "Emacs" };
-            println!("Current edit mode is: {}", mode_str);
+            xprintln!("Current edit mode is: {}", mode_str);
         }
         1 => match args[0].to_lowercase().as_ref() {
-            "vi" => app.rl.borrow_mut().set_edit_mode(EditMode::Vi),
-            "emacs" => app.rl.borrow_mut().set_edit_mode(EditMode::Emacs),
+            // applied to the editor when it reads the next line
+            "vi" => *app.edit_mode.lock().unwrap() = EditMode::Vi,
+            "emacs" => *app.edit_mode.lock().unwrap() = EditMode::Emacs,
             bad => {
                 return Err(XcliError::BadArgument(bad.into()));
             }
@@ -663,48 +2232,499 @@ fn cli_mode(app: &App, args: &[&str]) -> XcliResult {
     Ok(CmdExeCode::Ok)
 }
 
-#[macro_export]
-macro_rules! check_param {
-    ($param_count:expr, $required:expr, $args:ident, ($($change_type:ty=>$has_from:expr), *)) => {
-        {
-            // has_from seems unnecessary in this macro.
+/// Parse the job id in args, or take the latest job if not given
+fn job_from_args(app: &App, args: &[&str]) -> stdResult<Arc<Job>, XcliError> {
+    let job = match args.len() {
+        0 => app.jobs.all().pop(),
+        1 => app.jobs.get(Args::new(args).required::<usize>(0, "job id")?),
+        _ => return Err(XcliError::BadSyntax),
+    };
+
+    job.ok_or_else(|| XcliError::Other(format!("No such job: {}", args.first().unwrap_or(&"current"))))
+}
+
+/// Action of jobs command
+fn cli_jobs(app: &App, args: &[&str]) -> XcliResult {
+    if !args.is_empty() {
+        return Err(XcliError::BadSyntax);
+    }
+
+    for job in app.jobs.all() {
+        xprintln!("[{}] {:12} {}", job.id, job.status(), job.line);
+    }
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of fg command
+fn cli_fg(app: &App, args: &[&str]) -> XcliResult {
+    let job = job_from_args(app, args)?;
+    job::foreground(&app.jobs, &job)
+}
 
-            use $crate::XcliError;
-            // use std::string::ToString;
+/// Action of wait command
+fn cli_wait(app: &App, args: &[&str]) -> XcliResult {
+    let jobs = if args.is_empty() {
+        app.jobs.all()
+    } else {
+        vec![job_from_args(app, args)?]
+    };
+
+    // the result of the first job failed
+    let mut ret = Ok(CmdExeCode::Ok);
+    for job in jobs {
+        if !job::wait_foreground(&job, false) {
+            return Err(job::interrupted(&job));
+        }
+        xprintln!("[{}] {:12} {}", job.id, job.status(), job.line);
+        if let (Ok(_), Some(Err(err))) = (&ret, job.result()) {
+            ret = Err(err);
+        }
+    }
+    // the errors are shown in the status of the jobs
+    if ret.is_err() {
+        script::set_reported();
+    }
+    ret
+}
 
-            // Param count is less than required param.
-            if ($param_count < $required) {
-                return Err($crate::XcliError::MissingArgument)
-            }
+/// Action of kill command
+fn cli_kill(app: &App, args: &[&str]) -> XcliResult {
+    if args.is_empty() {
+        return Err(XcliError::MissingArgument);
+    }
 
-            // Param count is less than args.
-            if ($param_count < $args.len()) {
-                return Err($crate::XcliError::BadArgument(("Input is too long").to_string()))
-            }
+    let job = job_from_args(app, args)?;
+    job.cancel();
+    xprintln!("[{}] {:12} {}", job.id, job.status(), job.line);
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of output command
+fn cli_output(app: &App, args: &[&str]) -> XcliResult {
+    match args.len() {
+        0 => {
+            xprintln!("Output format is: {}", app.get_output_format());
+        }
+        1 => app.set_output_format(args[0].parse()?),
+        _ => return Err(XcliError::BadSyntax),
+    }
+
+    Ok(CmdExeCode::Ok)
+}
 
-            // Args count is less than required param.
-            if ($args.len() < $required) {
-                return Err($crate::XcliError::MismatchArgument($required, $args.len()))
+/// Action of tree command
+fn cli_tree(app: &App, args: &[&str]) -> XcliResult {
+    let mut path = vec![];
+    let mut depth = None;
+    let mut verbose = false;
+    let mut json = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = match args[i] {
+            "--verbose" => {
+                verbose = true;
+                None
+            }
+            "--json" => {
+                json = true;
+                None
+            }
+            "--depth" => {
+                i += 1;
+                Some(*args.get(i).ok_or(XcliError::MissingArgument)?)
+            }
+            arg if arg.starts_with("--depth=") => Some(&arg["--depth=".len()..]),
+            arg => {
+                path.push(arg);
+                None
             }
+        };
+        if let Some(value) = value {
+            let d = value
+                .parse::<usize>()
+                .map_err(|err| XcliError::BadArgument(format!("{}, {}", value, err)))?;
+            depth = Some(d);
+        }
+        i += 1;
+    }
 
-            let u = $args.clone().to_vec();
-            let mut number = 0;
+    let tree = app.visible_tree();
+    let cmd = tree
+        .locate_subcommand(&path)
+        .ok_or_else(|| XcliError::BadArgument(format!("{}, no such command", path.join(" "))))?;
+    let name = if path.is_empty() { app.get_name().to_string() } else { path.join(" ") };
+
+    if json && path.is_empty() {
+        xprintln!("{}", schema::to_json(app, &tree));
+    } else if json {
+        let out = serde_json::to_string_pretty(cmd).map_err(|err| XcliError::Other(err.to_string()))?;
+        xprintln!("{}", out);
+    } else {
+        xprint!("{}", help::tree(cmd, &name, depth, verbose));
+    }
+    Ok(CmdExeCode::Ok)
+}
 
-            (
-                $(
-                    {
-                        number += 1;
-                        // `try_from` the arg in [0, args.len()]
-                        if (number <= $args.len()) {
-                            let item = <$change_type>::try_from(u[number - 1]).map_err(|e|XcliError::BadArgument(e.to_string()))?;
-                            Some(item)
-                        } else {
-                            // return `None` if args.len() is less than param_count.
-                            None
-                        }
-                    },
-                )*
-            )
+/// Action of terminal length command
+fn cli_terminal_length(app: &App, args: &[&str]) -> XcliResult {
+    match args.len() {
+        0 => match app.get_terminal_length() {
+            Some(0) => xprintln!("Terminal length is: 0, paging is disabled"),
+            Some(n) => xprintln!("Terminal length is: {}", n),
+            None => xprintln!("Terminal length is: auto"),
+        },
+        1 if args[0] == "auto" => app.set_terminal_length(None),
+        1 => app.set_terminal_length(Some(Args::new(args).required::<usize>(0, "lines")?)),
+        _ => return Err(XcliError::BadSyntax),
+    }
+
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of enable command
+fn cli_enable(app: &App, args: &[&str]) -> XcliResult {
+    let args = Args::new(args);
+    args.at_most(1)?;
+    let level = args.optional::<u8>(0)?.unwrap_or(MAX_PRIVILEGE);
+
+    if level > app.get_privilege() {
+        let hook = app
+            .enable_hook
+            .ok_or_else(|| XcliError::PermissionDenied("enable is not configured".into()))?;
+        let password = app.ask_password("Password: ")?;
+        if !hook(app, level, &password) {
+            return Err(XcliError::PermissionDenied("bad password".into()));
         }
     }
-}
\ No newline at end of file
+    app.set_privilege(level);
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of disable command
+fn cli_disable(app: &App, args: &[&str]) -> XcliResult {
+    let args = Args::new(args);
+    args.at_most(1)?;
+    let level = args.optional::<u8>(0)?.unwrap_or(0);
+    if level > app.get_privilege() {
+        return Err(XcliError::BadArgument(format!("{}, higher than the current level", level)));
+    }
+    app.set_privilege(level);
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of exit command
+fn cli_exit(app: &App, args: &[&str]) -> XcliResult {
+    let args = Args::new(args);
+    args.at_most(1)?;
+    // in a command substitution or a background job, whose output is captured,
+    // exit ends it only, not the session
+    if let Some(code) = args.optional::<i32>(0)?.filter(|_| !output::is_capturing()) {
+        app.set_exit_code(code);
+    }
+    Ok(CmdExeCode::Exit)
+}
+
+/// Action of source command
+fn cli_source(app: &App, args: &[&str]) -> XcliResult {
+    if args.len() != 1 {
+        return Err(XcliError::MismatchArgument(1, args.len()));
+    }
+
+    let text = read_script(args[0])?;
+    script::source(app, &text)
+}
+
+/// Read a script file
+fn read_script(file: &str) -> stdResult<String, XcliError> {
+    std::fs::read_to_string(file).map_err(|err| XcliError::Other(format!("{}: {}", file, err)))
+}
+
+/// Action of set command
+fn cli_set(app: &App, args: &[&str]) -> XcliResult {
+    if args.is_empty() {
+        return Err(XcliError::MissingArgument);
+    }
+
+    app.set_var(args[0], args[1..].join(" "))?;
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of unset command
+fn cli_unset(app: &App, args: &[&str]) -> XcliResult {
+    if args.is_empty() {
+        return Err(XcliError::MissingArgument);
+    }
+
+    for name in args {
+        app.unset_var(name);
+    }
+    Ok(CmdExeCode::Ok)
+}
+
+/// Action of vars command
+fn cli_vars(app: &App, args: &[&str]) -> XcliResult {
+    if !args.is_empty() {
+        return Err(XcliError::BadSyntax);
+    }
+
+    for (name, value) in app.get_vars() {
+        xprintln!("{}={}", name, value);
+    }
+    Ok(CmdExeCode::Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_of_foreground_only() {
+        let app = App::new("test");
+        app.run_script("echo $(exit 3)").unwrap();
+        let (ret, _) = output::capture(|| app.run_script("watch -c 1 exit 5"));
+        assert!(ret.is_ok());
+        assert_eq!(app.exit_status().code(), 0);
+
+        assert_eq!(app.run_script("exit 4").unwrap(), CmdExeCode::Exit);
+        assert_eq!(app.exit_status().code(), 4);
+        assert!(app.exit_status().exited());
+    }
+
+    #[test]
+    fn exit_code_of_unknown_command() {
+        let (status, out) = output::capture(|| App::new("test").run_from_args(["xcli", "bogus", "x"]));
+        assert_eq!(status.code(), 127);
+        assert!(out.contains("Unknown command or arguments: bogus x"), "{}", out);
+    }
+
+    fn sensitive_app() -> App<'static> {
+        let mut app = App::new("test");
+        app.add_subcommand(
+            Command::new("passwd")
+                .sensitive_arg(1)
+                .sensitive_flag("--key")
+                .action(|_, _| Ok(CmdExeCode::Ok)),
+        );
+        app
+    }
+
+    #[test]
+    fn redact_line() {
+        let app = sensitive_app();
+        let redact = |line| app.redact_line(line);
+        assert_eq!(redact("passwd bob secret").unwrap(), "passwd bob ***");
+        assert_eq!(
+            redact("echo a; if passwd bob 's 2' --key=k; then echo ok; fi").unwrap(),
+            "echo a; if passwd bob *** --key=***; then echo ok; fi"
+        );
+        assert_eq!(redact("echo bob secret").unwrap(), "echo bob secret");
+        assert_eq!(redact("echo $x secret").unwrap(), "echo $x secret");
+
+        // quoted or escaped command names
+        assert_eq!(redact("'passwd' bob secret").unwrap(), "'passwd' bob ***");
+        assert_eq!(redact("pass\\wd bob secret").unwrap(), "pass\\wd bob ***");
+        assert_eq!(redact("passwd bob \"--key\" k").unwrap(), "passwd bob *** ***");
+
+        // substituted commands
+        assert_eq!(redact("echo $(passwd bob secret)").unwrap(), "echo $(passwd bob ***)");
+        assert_eq!(
+            redact("echo \"a $(echo $(passwd bob secret))\"").unwrap(),
+            "echo \"a $(echo $(passwd bob ***))\""
+        );
+
+        // not known before expanded
+        assert_eq!(redact("$cmd bob secret"), None);
+        assert_eq!(redact("passwd $user secret"), None);
+        assert_eq!(redact("echo $(p$x bob secret)"), None);
+    }
+
+    #[test]
+    fn audit_every_dispatch_once() {
+        let path = std::env::temp_dir().join(format!("xcli-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut app = sensitive_app().audit(Audit::to_file(&path).unwrap());
+        app.add_subcommand(Command::new("reboot").privilege(15).action(|_, _| Ok(CmdExeCode::Ok)));
+        output::capture(|| {
+            let _ = app.run_script("passwd bob secret; reboot; bogus x y");
+            let _ = app.run_script("echo a&b");
+        });
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let records: Vec<serde_json::Value> = log.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r["path"].as_str().unwrap(), r["args"].to_string(), r["result"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("passwd", r#"["bob","***"]"#.to_string(), "Ok"),
+                ("reboot", "[]".to_string(), "PermissionDenied"),
+                ("", r#"["bogus","***","***"]"#.to_string(), "UnknownCommand"),
+                ("", "[]".to_string(), "Other"),
+            ]
+        );
+    }
+
+    /// An app keeping the result seen by the middleware in the variable `seen`.
+    fn middleware_app() -> App<'static> {
+        App::new("test").after(|app, _, ret| {
+            let _ = app.set_var("seen", status::describe(ret));
+        })
+    }
+
+    /// Run a line, and get the result seen by the middleware of `middleware_app`.
+    fn seen_by_middleware(app: &App, line: &str) -> String {
+        app.unset_var("seen");
+        let _ = output::capture(|| app.run_script(line));
+        app.get_var("seen").unwrap_or_default()
+    }
+
+    #[test]
+    fn privilege_checked_in_middleware() {
+        let mut app = middleware_app();
+        app.add_subcommand(Command::new("reboot").privilege(15).action(|_, _| Ok(CmdExeCode::Ok)));
+        assert_eq!(seen_by_middleware(&app, "reboot"), "PermissionDenied");
+        assert!(!app.tree_json().contains("reboot"));
+    }
+
+    #[test]
+    fn confirm_in_middleware() {
+        let mut app = middleware_app();
+        app.add_subcommand(
+            Command::new("erase")
+                .confirm("Erase?")
+                .action(|_, args| match args {
+                    [] | ["--", "-y"] => Ok(CmdExeCode::Ok),
+                    _ => Err(XcliError::BadArgument(args.join(" "))),
+                }),
+        );
+        assert_eq!(seen_by_middleware(&app, "erase"), "Other");
+        assert_eq!(seen_by_middleware(&app, "erase -y"), "Ok");
+        assert_eq!(seen_by_middleware(&app, "erase --yes"), "Ok");
+        assert_eq!(seen_by_middleware(&app, "erase -y -- -y"), "Ok");
+    }
+
+    #[test]
+    fn attribute_flags_once() {
+        let cmd = Command::new("erase").confirm("Erase?").confirm("Erase all?").output_option().output_option();
+        assert_eq!(cmd.flags.len(), 2);
+        assert_eq!(cmd.confirm, Some("Erase all?"));
+    }
+
+    #[test]
+    fn choose_from_nothing() {
+        let app = App::new("test");
+        assert!(matches!(app.choose("Role", &[]), Err(XcliError::BadArgument(_))));
+    }
+
+    #[test]
+    fn command_tree_handle() {
+        let mut app = App::new("test");
+        app.add_subcommand(Command::new_with_alias("device", "dev"));
+        let tree = app.command_tree();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let eth1 = Command::new("eth1").action(|_, _| Ok(CmdExeCode::Ok));
+                tree.add_command(&["dev"], eth1).unwrap();
+            });
+        });
+        assert!(app.run_script("device eth1").is_ok());
+        assert!(app.tree().locate_subcommand(&["dev", "eth1"]).is_some());
+
+        assert!(tree.add_command(&["device"], Command::new("eth1")).is_err());
+        assert!(tree.remove_command(&["dev", "eth2"]).is_err());
+        tree.remove_command(&["dev", "eth1"]).unwrap();
+        assert!(app.tree().locate_subcommand(&["device", "eth1"]).is_none());
+
+        // names and aliases collide both ways
+        assert!(tree.add_command(&[], Command::new("dev")).is_err());
+        assert!(tree.add_command(&[], Command::new_with_alias("devices", "device")).is_err());
+        assert!(tree.add_command(&[], Command::new_with_alias("devices", "dev")).is_err());
+
+        app.add_subcommand_with_userdata(Command::new_with_alias("userdata", "ud"), Box::new(100usize));
+        assert_eq!(app.get_handler("userdata").unwrap().downcast_ref::<usize>(), Some(&100));
+        tree.remove_command(&["ud"]).unwrap();
+        assert!(matches!(app.get_handler("userdata"), Err(XcliError::MissingHandler(_))));
+    }
+
+    #[test]
+    fn disabled_in_middleware() {
+        let mut app = middleware_app();
+        app.add_subcommand(
+            Command::new_with_alias("device", "dev").subcommand(Command::new("reset").action(|_, _| Ok(CmdExeCode::Ok))),
+        );
+        app.disable_command(&["dev"]).unwrap();
+        let (ret, out) = output::capture(|| app.run_script("device reset"));
+        assert!(ret.is_err());
+        assert!(out.contains("'device' is disabled"), "{}", out);
+        assert_eq!(seen_by_middleware(&app, "device reset"), "Other");
+
+        app.enable_command(&["dev"]).unwrap();
+        assert_eq!(seen_by_middleware(&app, "device reset"), "Ok");
+    }
+
+    fn attributes_app() -> App<'static> {
+        let ok = |_: &App, _: &[&str]| Ok(CmdExeCode::Ok);
+        let mut app = App::new("test");
+        app.add_subcommand(Command::new("secret").about("a hidden command").hidden().action(ok));
+        app.add_subcommand(Command::new("turbo").about("an experimental command").experimental().action(ok));
+        app.add_subcommand(Command::new("count").action(|_, args| match args {
+            ["1"] => Ok(CmdExeCode::Ok),
+            _ => Err(XcliError::BadArgument(args.join(" "))),
+        }));
+        app.add_subcommand(Command::new("counter").deprecated("use 'count'").replaced_by("count").hidden());
+        app.add_subcommand(Command::new("ping").deprecated("a loop").replaced_by("pong"));
+        app.add_subcommand(Command::new("pong").deprecated("a loop").replaced_by("ping"));
+        app.add_subcommand(Command::new("halt").privilege(15).deprecated("use 'reboot'").replaced_by("count"));
+        app
+    }
+
+    #[test]
+    fn hidden_commands() {
+        let app = attributes_app();
+        assert!(app.run_script("secret").is_ok());
+        let (_, out) = output::capture(|| app.run_script("help"));
+        assert!(!out.contains("secret"), "{}", out);
+        assert!(!app.tree_json().contains("secret"));
+        assert!(!app.generate_doc(DocFormat::Markdown).contains("secret"));
+    }
+
+    #[test]
+    fn deprecated_commands() {
+        let app = attributes_app();
+        assert!(app.run_script("counter 1").is_ok());
+        assert!(matches!(app.run_script("counter 2"), Err(XcliError::BadArgument(_))));
+
+        let (ret, out) = output::capture(|| app.run_script("ping"));
+        assert!(ret.is_err());
+        assert!(out.contains("redirected too many times"), "{}", out);
+        assert_eq!(REDIRECTS.with(Cell::get), 0);
+        assert!(app.tree_json().contains(r#""replaced_by": "pong""#));
+
+        // checked before redirected
+        let (ret, _) = output::capture(|| app.run_script("halt 1"));
+        assert!(matches!(ret, Err(XcliError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn experimental_commands() {
+        let app = attributes_app();
+        let (ret, out) = output::capture(|| app.run_script("turbo"));
+        assert!(ret.is_err());
+        assert!(out.contains("'turbo' is experimental"), "{}", out);
+        let (_, out) = output::capture(|| app.run_script("help"));
+        assert!(!out.contains("turbo"), "{}", out);
+        assert!(!app.tree_json().contains("turbo"));
+        assert!(!app.generate_doc(DocFormat::Markdown).contains("turbo"));
+
+        app.set_experimental(true);
+        assert!(app.run_script("turbo").is_ok());
+        let (_, out) = output::capture(|| app.run_script("help"));
+        assert!(out.contains("turbo"), "{}", out);
+        assert!(app.tree_json().contains("turbo"));
+        assert!(app.generate_doc(DocFormat::Markdown).contains("turbo"));
+    }
+}
@@ -0,0 +1,80 @@
+//! Middleware around the actions of commands, e.g. for timing, audit logging,
+//! authorization checks, or transactions.
+
+use crate::{App, Command, XcliError, XcliResult};
+
+/// A command about to be executed, given to the middleware.
+#[derive(Clone)]
+pub struct Invocation<'i> {
+    pub(crate) command: &'i Command<'i>,
+    pub(crate) path: &'i [&'i str],
+    pub(crate) args: &'i [&'i str],
+}
+
+impl<'i> Invocation<'i> {
+    /// Get the command.
+    pub fn command(&self) -> &'i Command<'i> {
+        self.command
+    }
+
+    /// Get the path of the command, the names of the command and its parents,
+    /// e.g. `["terminal", "length"]`, with the aliases resolved.
+    pub fn path(&self) -> &'i [&'i str] {
+        self.path
+    }
+
+    /// Get the arguments of the command.
+    pub fn args(&self) -> &'i [&'i str] {
+        self.args
+    }
+
+    /// Get the command line, the path and the arguments joined by spaces,
+    /// with the sensitive arguments redacted, e.g. for logs.
+    pub fn line(&self) -> String {
+        let args = self.command.redact(self.args);
+        self.path.iter().copied().chain(args.iter().map(String::as_str)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// A hook called before the action, which is not executed if the hook returns an error.
+pub(crate) type BeforeHook = fn(&App, &Invocation) -> Result<(), XcliError>;
+
+/// A hook called after the action, with the result of it.
+pub(crate) type AfterHook = fn(&App, &Invocation, &XcliResult);
+
+/// A hook wrapping the action, which is executed by calling `next`.
+pub(crate) type AroundHook = fn(&App, &Invocation, next: &dyn Fn() -> XcliResult) -> XcliResult;
+
+/// All middleware registered to an app.
+#[derive(Default)]
+pub(crate) struct Middleware {
+    pub(crate) before: Vec<BeforeHook>,
+    pub(crate) after: Vec<AfterHook>,
+    pub(crate) around: Vec<AroundHook>,
+}
+
+impl Middleware {
+    /// Execute action with the middleware.
+    ///
+    /// Before hooks are called in the order registered, then the around hooks,
+    /// the first registered is the outermost, and the after hooks at last,
+    /// even if the action is vetoed.
+    pub(crate) fn run(&self, app: &App, invocation: &Invocation, action: &dyn Fn() -> XcliResult) -> XcliResult {
+        let ret = match self.before.iter().try_for_each(|hook| hook(app, invocation)) {
+            Ok(()) => around(&self.around, app, invocation, action),
+            Err(err) => Err(err),
+        };
+        for hook in &self.after {
+            hook(app, invocation, &ret);
+        }
+        ret
+    }
+}
+
+/// Call the around hooks, with the action innermost.
+fn around(hooks: &[AroundHook], app: &App, invocation: &Invocation, action: &dyn Fn() -> XcliResult) -> XcliResult {
+    match hooks.split_first() {
+        Some((hook, rest)) => hook(app, invocation, &|| around(rest, app, invocation, action)),
+        None => action(),
+    }
+}
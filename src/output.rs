@@ -0,0 +1,88 @@
+//! Output redirection for command actions.
+//!
+//! Everything xcli prints on behalf of a command goes through [`xprint!`] and
+//! [`xprintln!`]. By default this ends up on stdout, but the output can be
+//! captured into a buffer for the current thread, e.g. when a command runs
+//! as a background job. Output of foreground commands may go through the
+//! pager instead, see `terminal length`.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::pager;
+
+/// A shared buffer which receives captured output.
+pub(crate) type OutputBuffer = Arc<Mutex<Vec<u8>>>;
+
+thread_local! {
+    static CAPTURE: RefCell<Vec<OutputBuffer>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Redirect the output of the current thread into buf, until `end_capture` is called.
+pub(crate) fn begin_capture(buf: OutputBuffer) {
+    CAPTURE.with(|c| c.borrow_mut().push(buf));
+}
+
+/// Stop the innermost capture of the current thread, returning its buffer.
+pub(crate) fn end_capture() -> Option<OutputBuffer> {
+    CAPTURE.with(|c| c.borrow_mut().pop())
+}
+
+/// Call f with the output of the current thread captured, returns the result of f and the output.
+pub(crate) fn capture<F, R>(f: F) -> (R, String)
+where
+    F: FnOnce() -> R,
+{
+    let buf = OutputBuffer::default();
+    begin_capture(buf.clone());
+    let ret = f();
+    end_capture();
+
+    let output = String::from_utf8_lossy(&buf.lock().unwrap()).into_owned();
+    (ret, output)
+}
+
+/// Whether the output of the current thread is being captured.
+pub(crate) fn is_capturing() -> bool {
+    CAPTURE.with(|c| !c.borrow().is_empty())
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let captured = CAPTURE.with(|c| {
+        if let Some(buf) = c.borrow().last() {
+            let _ = buf.lock().unwrap().write_fmt(args);
+            true
+        } else {
+            false
+        }
+    });
+
+    if !captured && !pager::write(&args.to_string()) {
+        print!("{}", args);
+    }
+}
+
+/// Print to the CLI output, like `print!`.
+///
+/// Actions should prefer this over `print!` so that their output can be
+/// captured, e.g. when running as a background job.
+#[macro_export]
+macro_rules! xprint {
+    ($($arg:tt)*) => {
+        $crate::output::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to the CLI output with a newline, like `println!`.
+#[macro_export]
+macro_rules! xprintln {
+    () => {
+        $crate::xprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
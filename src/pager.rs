@@ -0,0 +1,198 @@
+//! A built-in pager for long output.
+//!
+//! When a command prints more lines than the terminal can show, the output
+//! pauses with a `--More--` prompt:
+//!
+//! * space shows the next page, enter shows one more line
+//! * `/pattern` skips to the next line which contains the pattern
+//! * `q` discards the rest of the output
+//!
+//! The pager works on output printed by `xprint!`/`xprintln!`, and it can be
+//! disabled by `terminal length 0`.
+
+use std::cell::RefCell;
+use std::io::{stdout, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+thread_local! {
+    static PAGER: RefCell<Option<Pager>> = const { RefCell::new(None) };
+}
+
+/// What to do with the lines to come.
+enum Mode {
+    /// Show the lines, page by page.
+    Page,
+    /// Skip the lines until one contains the pattern.
+    Search(String),
+    /// Discard all the rest.
+    Quit,
+}
+
+struct Pager {
+    /// The number of lines in a page.
+    page: usize,
+    /// The width of terminal, for wrapped lines.
+    width: usize,
+    /// The number of lines shown in the current page.
+    shown: usize,
+    mode: Mode,
+}
+
+/// Start paging the output of the calling thread, with page lines in a page.
+pub(crate) fn begin(page: usize) {
+    let width = terminal::size().ok().map(|(w, _)| w as usize).filter(|w| *w > 0).unwrap_or(80);
+    let pager = Pager {
+        page,
+        width,
+        shown: 0,
+        mode: Mode::Page,
+    };
+    PAGER.with(|p| *p.borrow_mut() = Some(pager));
+}
+
+/// Stop paging the output of the calling thread.
+pub(crate) fn end() {
+    if let Some(pager) = PAGER.with(|p| p.borrow_mut().take()) {
+        if let Mode::Search(pattern) = pager.mode {
+            println!("Pattern not found: {}", pattern);
+        }
+    }
+}
+
+/// Write s through the pager, returns false if the pager is not active.
+pub(crate) fn write(s: &str) -> bool {
+    PAGER.with(|p| match p.borrow_mut().as_mut() {
+        Some(pager) => {
+            pager.write(s);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Restore the terminal from raw mode when dropped.
+pub(crate) struct RawMode(());
+
+impl RawMode {
+    /// Enable raw mode, until the guard returned is dropped.
+    pub(crate) fn enable() -> std::io::Result<Self> {
+        terminal::enable_raw_mode().map(|_| RawMode(()))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Read a key press, in raw mode.
+pub(crate) fn read_key() -> Option<KeyEvent> {
+    loop {
+        match event::read().ok()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => return Some(key),
+            _ => {}
+        }
+    }
+}
+
+impl Pager {
+    fn write(&mut self, s: &str) {
+        let mut out = stdout();
+        for line in s.split_inclusive('\n') {
+            match &self.mode {
+                Mode::Quit => return,
+                Mode::Search(pattern) if !line.contains(pattern.as_str()) => continue,
+                Mode::Search(_) => {
+                    let _ = write!(out, "...skipping\n{}", line);
+                    self.mode = Mode::Page;
+                    self.shown = 2;
+                }
+                Mode::Page => {
+                    let _ = write!(out, "{}", line);
+                    if line.ends_with('\n') {
+                        // a long line is wrapped into more lines
+                        self.shown += line.trim_end().chars().count().max(1).div_ceil(self.width);
+                    }
+                }
+            }
+
+            if self.shown + 1 >= self.page {
+                self.prompt();
+            }
+        }
+        let _ = out.flush();
+    }
+
+    /// Show the `--More--` prompt, and wait for the user to decide what's next.
+    fn prompt(&mut self) {
+        let mut out = stdout();
+        let _ = write!(out, "\x1b[7m--More--\x1b[0m");
+        let _ = out.flush();
+
+        let _raw = match RawMode::enable() {
+            Ok(raw) => raw,
+            Err(_) => return,
+        };
+
+        loop {
+            let key = match read_key() {
+                Some(key) => key,
+                None => {
+                    self.mode = Mode::Quit;
+                    break;
+                }
+            };
+            match key.code {
+                KeyCode::Char(' ') => self.shown = 0,
+                KeyCode::Enter => self.shown = self.page.saturating_sub(2),
+                KeyCode::Char('q') | KeyCode::Esc => self.mode = Mode::Quit,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.mode = Mode::Quit,
+                KeyCode::Char('/') => match read_pattern() {
+                    Some(pattern) => {
+                        self.mode = Mode::Search(pattern);
+                        self.shown = 0;
+                    }
+                    None => {
+                        let _ = write!(out, "\r\x1b[K\x1b[7m--More--\x1b[0m");
+                        let _ = out.flush();
+                        continue;
+                    }
+                },
+                _ => continue,
+            }
+            break;
+        }
+
+        // erase the prompt
+        let _ = write!(out, "\r\x1b[K");
+        let _ = out.flush();
+    }
+}
+
+/// Read the pattern to search after '/', returns None if it is cancelled.
+fn read_pattern() -> Option<String> {
+    let mut out = stdout();
+    let mut pattern = String::new();
+    let _ = write!(out, "\r\x1b[K/");
+    let _ = out.flush();
+
+    loop {
+        let key = read_key()?;
+        match key.code {
+            KeyCode::Enter if !pattern.is_empty() => return Some(pattern),
+            KeyCode::Esc => return None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return None,
+            // backspace on the empty pattern cancels the search
+            KeyCode::Backspace => {
+                pattern.pop()?;
+            }
+            KeyCode::Char(c) => pattern.push(c),
+            _ => {}
+        }
+        let _ = write!(out, "\r\x1b[K/{}", pattern);
+        let _ = out.flush();
+    }
+}
@@ -0,0 +1,557 @@
+//! Command line parsing.
+//!
+//! A command line is first split into tokens, aka. operators and raw words
+//! which keep their quotes and `$` expressions. Then each word is expanded
+//! right before the command is executed:
+//!
+//! * `'...'` is taken literally, `"..."` allows variable expansion inside
+//! * `\` escapes the next character, e.g. `\$` is a literal `$`
+//! * `$NAME` and `${NAME}` are replaced by the value of the session variable
+//!   `NAME`, or the environment variable `NAME` if there is no such session
+//!   variable. Undefined variables expand to nothing.
+//! * `$?` is the status of the last command, see [`App::last_status`]
+//! * `$(command ...)` is replaced by the output of the command. Unless it is
+//!   double quoted, the output is split into words by whitespaces.
+//! * A word beginning with `{` or `[` extends to the matching bracket, with
+//!   whitespaces and newlines. It is taken literally, for JSON arguments.
+//!
+//! Unquoted `;`, `&&`, `||` and newline are operators even inside a word, e.g.
+//! `echo a;b` is two commands. A `&` in a word, e.g. `echo a&b`, is rejected,
+//! as it would run a part of the word in background. A `#` starts a comment
+//! only at the beginning of a word, e.g. `echo #1` prints nothing, while `port#1`
+//! is a word. Quote or escape them to have them taken literally.
+//!
+//! [`App::last_status`]: crate::App::last_status
+
+use crate::{App, XcliError};
+
+/// Whether c can be part of a variable name.
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Check if s is a valid variable name.
+pub(crate) fn is_valid_name(s: &str) -> bool {
+    !s.is_empty() && !s.starts_with(|c: char| c.is_ascii_digit()) && s.chars().all(is_name_char)
+}
+
+/// Why an expression can't be scanned.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ScanError {
+    /// A quote or bracket is not terminated.
+    Unterminated,
+    /// A `\` at the end, out of single quotes, which escapes the newline.
+    Escape,
+    /// A closing bracket doesn't match the opening one.
+    Unbalanced(char),
+    /// A `&` in a word.
+    Ampersand,
+}
+
+impl From<ScanError> for XcliError {
+    fn from(err: ScanError) -> Self {
+        match err {
+            ScanError::Unterminated | ScanError::Escape => XcliError::BadSyntax,
+            ScanError::Unbalanced(c) => XcliError::Other(format!("Bad syntax: unbalanced '{}'", c)),
+            ScanError::Ampersand => {
+                XcliError::Other("Bad syntax: '&' in a word, quote or escape it, or separate it by spaces".into())
+            }
+        }
+    }
+}
+
+/// Get the closing bracket of c, if c is an opening bracket.
+fn closing_bracket(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+/// Find the end of the quoted string, escape, bracketed text or `$`
+/// expression starting at s[i..], e.g. `'...'`, `"..."`, `\c`, `{...}`,
+/// `${...}` or `$(...)`.
+///
+/// Returns the index right after it.
+fn scan(s: &str, i: usize) -> Result<usize, ScanError> {
+    let rest = &s[i..];
+    if let Some(escaped) = rest.strip_prefix('\\') {
+        return escaped.chars().next().map(|c| i + 1 + c.len_utf8()).ok_or(ScanError::Escape);
+    }
+    if let Some(quoted) = rest.strip_prefix('\'') {
+        return quoted.find('\'').map(|end| i + end + 2).ok_or(ScanError::Unterminated);
+    }
+    if rest.starts_with("${") {
+        return rest.find('}').map(|end| i + end + 1).ok_or(ScanError::Unterminated);
+    }
+
+    let first = rest.chars().next().unwrap_or(' ');
+    let (close, mut j) = if rest.starts_with("$(") {
+        (')', i + 2)
+    } else if first == '"' {
+        ('"', i + 1)
+    } else if let Some(close) = closing_bracket(first) {
+        (close, i + 1)
+    } else {
+        return Ok(i + first.len_utf8());
+    };
+
+    while let Some(c) = s[j..].chars().next() {
+        if c == close {
+            return Ok(j + 1);
+        }
+        j = match c {
+            '\\' | '$' => scan(s, j)?,
+            // in double quotes, only escapes and '$' are special
+            _ if close == '"' => j + c.len_utf8(),
+            '\'' | '"' | '(' | '[' | '{' => scan(s, j)?,
+            ')' | ']' | '}' => return Err(ScanError::Unbalanced(c)),
+            c => j + c.len_utf8(),
+        };
+    }
+    Err(ScanError::Unterminated)
+}
+
+/// A token of a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    /// A raw word, with quotes and escapes kept.
+    Word(String),
+    /// An operator, one of `;`, `&&`, `||`, `&` and newline.
+    Op(&'static str),
+}
+
+/// A token, with the range of the text it comes from.
+pub(crate) type Spanned = (Token, usize, usize);
+
+/// Split text into tokens, with quotes and escapes in words kept.
+///
+/// A `#` at the beginning of a word starts a comment, till the end of line.
+pub(crate) fn tokenize(text: &str) -> Result<Vec<Spanned>, XcliError> {
+    Ok(scan_tokens(text)?)
+}
+
+/// Split text into tokens, as `tokenize`, with the reason why it can't be.
+fn scan_tokens(text: &str) -> Result<Vec<Spanned>, ScanError> {
+    let mut tokens = vec![];
+    let mut start = None;
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        let op = match c {
+            '\n' => Some("\n"),
+            ';' => Some(";"),
+            '&' if text[i + 1..].starts_with('&') => Some("&&"),
+            '&' if start.is_some() && text[i + 1..].starts_with(|c: char| !c.is_whitespace() && c != ';') => {
+                return Err(ScanError::Ampersand);
+            }
+            '&' => Some("&"),
+            '|' if text[i + 1..].starts_with('|') => Some("||"),
+            _ => None,
+        };
+
+        if op.is_some() || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push((Token::Word(text[start..i].to_string()), start, i));
+            }
+            if let Some(op) = op {
+                tokens.push((Token::Op(op), i, i + op.len()));
+                i += op.len();
+            } else {
+                i += c.len_utf8();
+            }
+            continue;
+        }
+
+        if c == '#' && start.is_none() {
+            i = text[i..].find('\n').map_or(text.len(), |end| i + end);
+            continue;
+        }
+
+        // brackets group a word only at its beginning, e.g. for JSON arguments
+        let group = start.is_none() && matches!(c, '[' | '{');
+        start.get_or_insert(i);
+        i = match c {
+            '\\' | '\'' | '"' | '$' => scan(text, i)?,
+            _ if group => scan(text, i)?,
+            c => i + c.len_utf8(),
+        };
+    }
+
+    if let Some(start) = start {
+        tokens.push((Token::Word(text[start..].to_string()), start, text.len()));
+    }
+    Ok(tokens)
+}
+
+/// Expand the variable at the beginning of s, which follows a '$'.
+///
+/// Returns the expanded value and the length of s consumed.
+fn expand_var(app: &App, s: &str) -> Result<(String, usize), XcliError> {
+    if s.starts_with('?') {
+        return Ok((app.last_status(), 1));
+    }
+
+    if let Some(rest) = s.strip_prefix('{') {
+        let end = rest.find('}').ok_or(XcliError::BadSyntax)?;
+        let name = &rest[..end];
+        if !is_valid_name(name) {
+            return Err(XcliError::BadArgument(format!("${{{}}}, bad variable name", name)));
+        }
+        return Ok((app.lookup_var(name).unwrap_or_default(), end + 2));
+    }
+
+    let len = s.find(|c: char| !is_name_char(c)).unwrap_or(s.len());
+    if len == 0 || !is_valid_name(&s[..len]) {
+        // not a variable, '$' is taken literally
+        return Ok(("$".to_string(), 0));
+    }
+    Ok((app.lookup_var(&s[..len]).unwrap_or_default(), len))
+}
+
+/// The words expanded from a raw word.
+#[derive(Default)]
+struct Fields {
+    fields: Vec<String>,
+    current: String,
+    quoted: bool,
+}
+
+impl Fields {
+    /// Finish the current field, if there is any.
+    fn split(&mut self) {
+        if !self.current.is_empty() || self.quoted {
+            self.fields.push(std::mem::take(&mut self.current));
+        }
+        self.quoted = false;
+    }
+
+    /// Append s to the current field, with words separated by whitespaces split into fields.
+    fn push_split(&mut self, s: &str) {
+        if s.starts_with(char::is_whitespace) {
+            self.split();
+        }
+        for (i, word) in s.split_whitespace().enumerate() {
+            if i > 0 {
+                self.split();
+            }
+            self.current.push_str(word);
+        }
+        if s.ends_with(char::is_whitespace) {
+            self.split();
+        }
+    }
+}
+
+/// Expand a raw word into words.
+///
+/// An unquoted word which expands to nothing is dropped.
+pub(crate) fn expand_word(app: &App, word: &str) -> Result<Vec<String>, XcliError> {
+    let mut f = Fields::default();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while let Some(c) = word[i..].chars().next() {
+        i += c.len_utf8();
+        match c {
+            '[' | '{' if i == 1 => {
+                // bracketed text is taken literally
+                let end = scan(word, 0)?;
+                f.current.push_str(&word[..end]);
+                f.quoted = true;
+                i = end;
+            }
+            '\\' => {
+                let n = word[i..].chars().next().ok_or(XcliError::BadSyntax)?;
+                i += n.len_utf8();
+                // in double quotes, only a few characters can be escaped
+                if in_double && !matches!(n, '$' | '"' | '\\') {
+                    f.current.push(c);
+                }
+                f.current.push(n);
+            }
+            '\'' if !in_double => {
+                let end = word[i..].find('\'').ok_or(XcliError::BadSyntax)?;
+                f.current.push_str(&word[i..i + end]);
+                f.quoted = true;
+                i += end + 1;
+            }
+            '"' => {
+                in_double = !in_double;
+                f.quoted = true;
+            }
+            '$' if word[i..].starts_with('(') => {
+                let end = scan(word, i - 1)?;
+                let output = app.capture_line(&word[i + 1..end - 1])?;
+                if in_double {
+                    f.current.push_str(&output);
+                } else {
+                    f.push_split(&output);
+                }
+                i = end;
+            }
+            '$' => {
+                let (value, len) = expand_var(app, &word[i..])?;
+                f.current.push_str(&value);
+                i += len;
+            }
+            c => f.current.push(c),
+        }
+    }
+
+    f.split();
+    Ok(f.fields)
+}
+
+/// Remove the quotes and escapes of a raw word, without expanding it, e.g. for
+/// logs. Returns None if it has any `$` expression, whose value is not known
+/// until it is expanded.
+pub(crate) fn literal_word(word: &str) -> Option<String> {
+    let mut literal = String::new();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while let Some(c) = word[i..].chars().next() {
+        i += c.len_utf8();
+        match c {
+            '[' | '{' if i == 1 => {
+                let end = scan(word, 0).ok()?;
+                literal.push_str(&word[..end]);
+                i = end;
+            }
+            '\\' => {
+                let n = word[i..].chars().next()?;
+                i += n.len_utf8();
+                if in_double && !matches!(n, '$' | '"' | '\\') {
+                    literal.push(c);
+                }
+                literal.push(n);
+            }
+            '\'' if !in_double => {
+                let end = word[i..].find('\'')?;
+                literal.push_str(&word[i..i + end]);
+                i += end + 1;
+            }
+            '"' => in_double = !in_double,
+            '$' => return None,
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+/// Find the commands substituted in a raw word, i.e. the text in `$(...)`,
+/// returns their ranges in the word.
+pub(crate) fn substitutions(word: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut in_double = false;
+    let mut i = 0;
+
+    while let Some(c) = word[i..].chars().next() {
+        let start = i;
+        i += c.len_utf8();
+        let end = match c {
+            '[' | '{' if start == 0 => scan(word, 0),
+            '\\' => scan(word, start),
+            '\'' if !in_double => scan(word, start),
+            '"' => {
+                in_double = !in_double;
+                continue;
+            }
+            '$' if word[i..].starts_with('(') => {
+                let end = scan(word, start);
+                if let Ok(end) = end {
+                    ranges.push((start + 2, end - 1));
+                }
+                end
+            }
+            _ => continue,
+        };
+        match end {
+            Ok(end) => i = end,
+            Err(_) => break,
+        }
+    }
+    ranges
+}
+
+/// Expand raw words into arguments.
+pub(crate) fn expand_words(app: &App, words: &[String]) -> Result<Vec<String>, XcliError> {
+    let mut args = vec![];
+    for word in words {
+        args.extend(expand_word(app, word)?);
+    }
+    Ok(args)
+}
+
+/// Whether the input is complete, as checked by `check_input`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Completeness {
+    Complete,
+    /// The input is to be continued in the next line, with the newline in
+    /// the quoted or bracketed text.
+    Incomplete,
+    /// The input ends with a `\` escaping the newline, which is removed when
+    /// it is continued.
+    Escaped,
+    /// The input is bad, with a message.
+    Invalid(String),
+}
+
+/// Check if the input ends with an escaped newline, unterminated quote or
+/// unbalanced brackets, which means it is to be continued.
+pub(crate) fn check_input(text: &str) -> Completeness {
+    match scan_tokens(text) {
+        Ok(_) => Completeness::Complete,
+        Err(ScanError::Unterminated) => Completeness::Incomplete,
+        Err(ScanError::Escape) => Completeness::Escaped,
+        Err(err) => Completeness::Invalid(XcliError::from(err).to_string()),
+    }
+}
+
+impl Completeness {
+    /// Whether the input is to be continued, see `join_lines`.
+    pub(crate) fn is_incomplete(&self) -> bool {
+        matches!(self, Completeness::Incomplete | Completeness::Escaped)
+    }
+}
+
+/// Join the continuation line to the input.
+///
+/// A trailing '\\' escaping the newline is removed along with it, otherwise
+/// the newline is kept, which is part of the quoted or bracketed text, e.g.
+/// a '\\' in single quotes is taken literally.
+pub(crate) fn join_lines(input: &mut String, line: &str) {
+    if check_input(input) == Completeness::Escaped {
+        input.pop();
+    } else {
+        input.push('\n');
+    }
+    input.push_str(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _, _)| match token {
+                Token::Word(w) => w,
+                Token::Op(op) => op.to_string(),
+            })
+            .collect()
+    }
+
+    fn expand(app: &App, word: &str) -> Vec<String> {
+        expand_word(app, word).unwrap()
+    }
+
+    #[test]
+    fn tokenize_words_and_operators() {
+        assert_eq!(words("a  b\tc"), ["a", "b", "c"]);
+        assert_eq!(words("a;b && c || d &"), ["a", ";", "b", "&&", "c", "||", "d", "&"]);
+        assert_eq!(words("a\nb"), ["a", "\n", "b"]);
+        assert_eq!(words("a # comment\nb"), ["a", "\n", "b"]);
+        assert_eq!(words("port#1"), ["port#1"]);
+    }
+
+    #[test]
+    fn tokenize_quotes_and_escapes() {
+        assert_eq!(words(r#"echo 'a b' "c d" e\ f"#), ["echo", "'a b'", "\"c d\"", r"e\ f"]);
+        assert_eq!(words(r#"'a;b' "a&b" a\;b"#), ["'a;b'", "\"a&b\"", r"a\;b"]);
+        assert_eq!(words("$(echo a b) ${x}y"), ["$(echo a b)", "${x}y"]);
+    }
+
+    #[test]
+    fn tokenize_brackets() {
+        assert_eq!(words(r#"set {"a": [1, 2]} x"#), ["set", r#"{"a": [1, 2]}"#, "x"]);
+        assert_eq!(words("[1,\n 2]"), ["[1,\n 2]"]);
+        // brackets group a word only at its beginning
+        assert_eq!(words("a[1 2]"), ["a[1", "2]"]);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert!(matches!(tokenize("'a"), Err(XcliError::BadSyntax)));
+        assert!(matches!(tokenize("\"a"), Err(XcliError::BadSyntax)));
+        assert!(matches!(tokenize("a\\"), Err(XcliError::BadSyntax)));
+        assert!(matches!(tokenize("$(a"), Err(XcliError::BadSyntax)));
+        assert!(matches!(tokenize("{a"), Err(XcliError::BadSyntax)));
+        assert!(matches!(tokenize("{a)"), Err(XcliError::Other(_))));
+        assert!(matches!(tokenize("echo a&b"), Err(XcliError::Other(_))));
+    }
+
+    #[test]
+    fn expand_quotes_and_escapes() {
+        let app = App::new("test");
+        assert_eq!(expand(&app, "'a $x'"), ["a $x"]);
+        assert_eq!(expand(&app, r#""a\"b\n""#), [r#"a"b\n"#]);
+        assert_eq!(expand(&app, r"a\ b\$"), ["a b$"]);
+        assert_eq!(expand(&app, "''"), [""]);
+        assert_eq!(expand(&app, r#"{"a": 1}"#), [r#"{"a": 1}"#]);
+    }
+
+    #[test]
+    fn expand_variables() {
+        let app = App::new("test");
+        app.set_var("x", "1 2").unwrap();
+        assert_eq!(expand(&app, "$x"), ["1 2"]);
+        assert_eq!(expand(&app, "${x}3"), ["1 23"]);
+        assert_eq!(expand(&app, "\"$x\""), ["1 2"]);
+        assert_eq!(expand(&app, "'$x'"), ["$x"]);
+        assert_eq!(expand(&app, "$"), ["$"]);
+        assert_eq!(expand(&app, "a$1"), ["a$1"]);
+        // undefined variables expand to nothing, and the word is dropped
+        assert!(expand(&app, "$xcli_undefined").is_empty());
+        assert!(expand_word(&app, "${1x}").is_err());
+    }
+
+    #[test]
+    fn expand_status_and_commands() {
+        let app = App::new("test");
+        assert_eq!(expand(&app, "$?"), ["Ok"]);
+        assert_eq!(expand(&app, "$(echo a  b)"), ["a", "b"]);
+        assert_eq!(expand(&app, "\"$(echo a  b)\""), ["a b"]);
+        assert!(expand_word(&app, "$(log bogus)").is_err());
+        assert_eq!(app.last_status(), "BadArgument");
+    }
+
+    #[test]
+    fn check_incomplete_input() {
+        assert_eq!(check_input("echo a"), Completeness::Complete);
+        assert_eq!(check_input(r"echo a\\"), Completeness::Complete);
+        assert_eq!(check_input("echo a\\"), Completeness::Escaped);
+        assert_eq!(check_input("echo \"a\\"), Completeness::Escaped);
+        assert_eq!(check_input("echo 'a\\"), Completeness::Incomplete);
+        assert_eq!(check_input("echo 'a"), Completeness::Incomplete);
+        assert_eq!(check_input("echo \"a 'b"), Completeness::Incomplete);
+        assert_eq!(check_input("set x {\"a\": [1,"), Completeness::Incomplete);
+        assert_eq!(check_input("echo $(echo a"), Completeness::Incomplete);
+        assert!(matches!(check_input("set x {]"), Completeness::Invalid(_)));
+    }
+
+    #[test]
+    fn join_continuation_lines() {
+        let mut line = "echo a\\".to_string();
+        join_lines(&mut line, "b");
+        assert_eq!(line, "echo ab");
+
+        // backslashes are taken literally in single quotes, even or odd
+        let mut line = r"echo 'a\\".to_string();
+        join_lines(&mut line, "b'");
+        assert_eq!(line, "echo 'a\\\\\nb'");
+        let mut line = "echo 'a\\".to_string();
+        join_lines(&mut line, "b'");
+        assert_eq!(line, "echo 'a\\\nb'");
+
+        let mut line = "set x [1,".to_string();
+        join_lines(&mut line, "2]");
+        assert_eq!(line, "set x [1,\n2]");
+        assert_eq!(check_input(&line), Completeness::Complete);
+    }
+}
@@ -0,0 +1,101 @@
+//! Prompts asking the user for input while a command is running.
+
+use std::io::{stdin, stdout, IsTerminal, Write};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use crate::job::Job;
+use crate::pager::{read_key, RawMode};
+use crate::{script, XcliError};
+
+/// Read a line from stdin, when it is not a terminal, e.g. a pipe.
+fn read_stdin_line() -> Result<String, XcliError> {
+    let mut line = String::new();
+    if stdin().read_line(&mut line)? == 0 {
+        return Err(XcliError::Other("Input is closed".into()));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Whether the user can be asked, i.e. stdin is a terminal, and the command
+/// is not running as a background job, nor in a sourced script.
+pub(crate) fn is_interactive() -> bool {
+    stdin().is_terminal() && !Job::in_background() && !script::is_sourcing()
+}
+
+/// Whether arg is the option to skip the confirmation.
+fn is_yes_option(arg: &str) -> bool {
+    arg == "--yes" || arg == "-y"
+}
+
+/// Take `--yes` or `-y` out of args, returns the rest, and whether it is given.
+///
+/// Only the first or the last argument is taken, but not the one after `--`,
+/// so that arguments like `-y` in the middle are left to the command.
+pub(crate) fn take_yes_option(args: Vec<&str>) -> (Vec<&str>, bool) {
+    let mut rest = args;
+    if rest.first().is_some_and(|a| is_yes_option(a)) {
+        rest.remove(0);
+        return (rest, true);
+    }
+    let trailing = match rest.split_last() {
+        Some((last, init)) => is_yes_option(last) && !init.contains(&"--"),
+        None => false,
+    };
+    if trailing {
+        rest.pop();
+    }
+    (rest, trailing)
+}
+
+/// Read a password after the prompt, which is not echoed.
+///
+/// Ctrl-C or Esc cancels it.
+pub(crate) fn read_password(prompt: &str) -> Result<String, XcliError> {
+    // no prompt on non-tty device, as the CLI loop
+    if !stdin().is_terminal() {
+        return read_stdin_line();
+    }
+    let mut out = stdout();
+    write!(out, "{}", prompt)?;
+    out.flush()?;
+
+    let mut password = String::new();
+    let cancelled = {
+        let _raw = RawMode::enable()?;
+        loop {
+            let Some(key) = read_key() else { break true };
+            match key.code {
+                KeyCode::Enter => break false,
+                KeyCode::Esc => break true,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break true,
+                KeyCode::Backspace => {
+                    password.pop();
+                }
+                KeyCode::Char(c) => password.push(c),
+                _ => {}
+            }
+        }
+    };
+    writeln!(out)?;
+
+    if cancelled {
+        return Err(XcliError::Other("Cancelled".into()));
+    }
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yes_option() {
+        assert_eq!(take_yes_option(vec!["-y", "a"]), (vec!["a"], true));
+        assert_eq!(take_yes_option(vec!["a", "--yes"]), (vec!["a"], true));
+        assert_eq!(take_yes_option(vec!["a", "-y", "b"]), (vec!["a", "-y", "b"], false));
+        assert_eq!(take_yes_option(vec!["--", "a", "-y"]), (vec!["--", "a", "-y"], false));
+        assert_eq!(take_yes_option(vec!["-y", "-y"]), (vec!["-y"], true));
+        assert_eq!(take_yes_option(vec![]), (vec![], false));
+    }
+}
@@ -0,0 +1,72 @@
+//! A machine-readable schema of the command tree.
+//!
+//! `Command` is serialized with its names, descriptions, usage, documented
+//! arguments and flags, examples and subcommands, so that external tools can
+//! introspect the CLI, e.g. by the output of `tree --json`.
+
+use serde::ser::{Serialize, SerializeMap, SerializeStruct, Serializer};
+
+use crate::{App, Command};
+
+/// A documented item, e.g. an argument or a flag, serialized as a map.
+struct Item<'s>(&'static str, &'s str, &'s str);
+
+impl Serialize for Item<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(self.0, self.1)?;
+        map.serialize_entry("help", self.2)?;
+        map.end()
+    }
+}
+
+/// Make a list of documented items.
+fn items<'s>(key: &'static str, items: &'s [(&str, &str)]) -> Vec<Item<'s>> {
+    items.iter().map(|(name, help)| Item(key, name, help)).collect()
+}
+
+impl Serialize for Command<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Command", 17)?;
+        s.serialize_field("name", &self.name)?;
+        s.serialize_field("alias", &self.alias)?;
+        s.serialize_field("about", &self.about)?;
+        s.serialize_field("usage", &self.usage)?;
+        s.serialize_field("long_about", &self.long_about)?;
+        // whether the command has an action, otherwise it only groups subcommands
+        s.serialize_field("runnable", &self.action.is_some())?;
+        s.serialize_field("args", &items("name", &self.args))?;
+        s.serialize_field("flags", &items("flag", &self.flags))?;
+        s.serialize_field("examples", &items("command", &self.examples))?;
+        s.serialize_field("see_also", &self.see_also)?;
+        s.serialize_field("privilege", &self.privilege)?;
+        s.serialize_field("confirm", &self.confirm)?;
+        s.serialize_field("hidden", &self.hidden)?;
+        s.serialize_field("deprecated", &self.deprecated)?;
+        s.serialize_field("replaced_by", &self.replaced_by)?;
+        s.serialize_field("experimental", &self.experimental)?;
+        s.serialize_field("subcommands", &self.subcommands)?;
+        s.end()
+    }
+}
+
+/// The schema of a whole CLI, with its command tree.
+struct Schema<'s, 'a>(&'s App<'a>, &'s Command<'a>);
+
+impl Serialize for Schema<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (app, tree) = (self.0, self.1);
+        let mut s = serializer.serialize_struct("App", 5)?;
+        s.serialize_field("name", app.get_name())?;
+        s.serialize_field("version", &app.version)?;
+        s.serialize_field("author", &app.author)?;
+        s.serialize_field("about", &tree.about)?;
+        s.serialize_field("commands", &tree.subcommands)?;
+        s.end()
+    }
+}
+
+/// Get the schema of app with the command tree as pretty printed JSON.
+pub(crate) fn to_json<'a>(app: &App<'a>, tree: &Command<'a>) -> String {
+    serde_json::to_string_pretty(&Schema(app, tree)).unwrap_or_default()
+}
@@ -0,0 +1,518 @@
+//! Scripts, aka. command lines with control flow.
+//!
+//! Commands can be chained by `;` or newline, or conditionally by `&&` and
+//! `||`, depending on whether the previous command returned `Ok` or `Err`.
+//! A command, or a chain of commands, ending with `&` runs in background.
+//!
+//! Besides, there are `if` and `for` compound commands:
+//!
+//! ```text
+//! if <commands>; then <commands>; [elif <commands>; then <commands>;] [else <commands>;] fi
+//! for <NAME> in <words...>; do <commands>; done
+//! ```
+//!
+//! The condition of `if` is true when its last command returns `Ok`. The loop
+//! variable of `for` is a session variable, which can be referred as `$NAME`.
+//!
+//! The keywords `if`, `then`, `elif`, `else`, `fi`, `for`, `do` and `done` are
+//! recognized only as the first word of a command, e.g. `echo done` prints
+//! `done`. Quote it to execute a command named as a keyword, e.g. `'do' it`.
+
+use std::cell::Cell;
+
+use crate::parser::{self, Spanned, Token};
+use crate::{App, CmdExeCode, XcliError, XcliResult};
+
+/// How deep scripts can be sourced by scripts.
+const MAX_SOURCE_DEPTH: usize = 16;
+
+thread_local! {
+    /// The depth of the scripts being sourced on this thread.
+    static SOURCE_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Whether the error returned by a sourced script has been reported.
+    static REPORTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Keywords which end a list of commands.
+const TERMINATORS: [&str; 6] = ["then", "elif", "else", "fi", "do", "done"];
+
+/// A command.
+enum Node {
+    /// A simple command, with raw words.
+    Simple(Vec<String>),
+    /// if / elif / else.
+    If {
+        cond: List,
+        then: List,
+        otherwise: Option<List>,
+    },
+    /// for loop.
+    For { var: String, words: Vec<String>, body: List },
+}
+
+/// How a command is chained to the previous one.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Chain {
+    And,
+    Or,
+}
+
+/// Commands chained by `&&` or `||`.
+struct AndOr {
+    first: Node,
+    rest: Vec<(Chain, Node)>,
+}
+
+/// An item of a command list.
+struct Item {
+    commands: AndOr,
+    /// The text of the commands, for a background job.
+    text: String,
+    background: bool,
+}
+
+impl Item {
+    /// Make an item of a single command, run in foreground.
+    fn new(node: Node) -> Self {
+        Item {
+            commands: AndOr {
+                first: node,
+                rest: vec![],
+            },
+            text: String::new(),
+            background: false,
+        }
+    }
+}
+
+/// Commands separated by `;`, `&` or newline.
+type List = Vec<Item>;
+
+/// Make a syntax error.
+fn syntax_error(what: &str) -> XcliError {
+    XcliError::Other(format!("Bad syntax: unexpected {}", what))
+}
+
+/// A recursive descent parser of scripts.
+struct Parser<'t> {
+    text: &'t str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn new(text: &'t str) -> Result<Self, XcliError> {
+        Ok(Parser {
+            text,
+            tokens: parser::tokenize(text)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    /// Get the keyword at current position, if any.
+    fn keyword(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Word(w)) if ["if", "for", "in"].contains(&w.as_str()) || TERMINATORS.contains(&w.as_str()) => {
+                Some(w.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// Describe the token at current position, for error messages.
+    fn describe(&self) -> String {
+        match self.peek() {
+            Some(Token::Word(w)) => format!("'{}'", w),
+            Some(Token::Op("\n")) => "newline".to_string(),
+            Some(Token::Op(op)) => format!("'{}'", op),
+            None => "end of input".to_string(),
+        }
+    }
+
+    /// Consume the keyword expected.
+    fn expect(&mut self, keyword: &str) -> Result<(), XcliError> {
+        if self.keyword() == Some(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(syntax_error(&format!("{}, '{}' expected", self.describe(), keyword)))
+        }
+    }
+
+    /// Skip separators, and optionally newlines only.
+    fn skip_separators(&mut self, newline_only: bool) {
+        while let Some(Token::Op(op)) = self.peek() {
+            if *op == "\n" || (*op == ";" && !newline_only) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Parse a list of commands, until one of the terminators or the end.
+    fn list(&mut self, terminators: &[&str]) -> Result<List, XcliError> {
+        let mut list = vec![];
+        loop {
+            self.skip_separators(false);
+            match self.keyword() {
+                Some(k) if terminators.contains(&k) => break,
+                Some(k) if TERMINATORS.contains(&k) => return Err(syntax_error(&self.describe())),
+                _ => {}
+            }
+            if self.peek().is_none() {
+                break;
+            }
+
+            let start = self.tokens[self.pos].1;
+            let commands = self.and_or()?;
+            let end = self.tokens[self.pos - 1].2;
+
+            let background = self.peek() == Some(&Token::Op("&"));
+            match self.peek() {
+                Some(Token::Op("&")) | Some(Token::Op(";")) | Some(Token::Op("\n")) => self.pos += 1,
+                None => {}
+                _ => return Err(syntax_error(&self.describe())),
+            }
+
+            list.push(Item {
+                commands,
+                text: self.text[start..end].to_string(),
+                background,
+            });
+        }
+        Ok(list)
+    }
+
+    /// Parse commands chained by `&&` or `||`.
+    fn and_or(&mut self) -> Result<AndOr, XcliError> {
+        let first = self.command()?;
+        let mut rest = vec![];
+        loop {
+            let chain = match self.peek() {
+                Some(Token::Op("&&")) => Chain::And,
+                Some(Token::Op("||")) => Chain::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_separators(true);
+            rest.push((chain, self.command()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    /// Parse a simple or compound command.
+    fn command(&mut self) -> Result<Node, XcliError> {
+        match self.keyword() {
+            Some("if") => {
+                self.pos += 1;
+                return self.if_body();
+            }
+            Some("for") => {
+                self.pos += 1;
+                return self.for_body();
+            }
+            _ => {}
+        }
+
+        let mut words = vec![];
+        while let Some(Token::Word(w)) = self.peek() {
+            words.push(w.clone());
+            self.pos += 1;
+        }
+        if words.is_empty() {
+            return Err(syntax_error(&self.describe()));
+        }
+        Ok(Node::Simple(words))
+    }
+
+    /// Parse an if command, after 'if' or 'elif'.
+    fn if_body(&mut self) -> Result<Node, XcliError> {
+        let cond = self.list(&["then"])?;
+        self.expect("then")?;
+        let then = self.list(&["elif", "else", "fi"])?;
+
+        let otherwise = match self.keyword() {
+            Some("elif") => {
+                self.pos += 1;
+                // the nested if consumes 'fi'
+                let nested = self.if_body()?;
+                return Ok(Node::If {
+                    cond,
+                    then,
+                    otherwise: Some(vec![Item::new(nested)]),
+                });
+            }
+            Some("else") => {
+                self.pos += 1;
+                Some(self.list(&["fi"])?)
+            }
+            _ => None,
+        };
+        self.expect("fi")?;
+        Ok(Node::If { cond, then, otherwise })
+    }
+
+    /// Parse a for command, after 'for'.
+    fn for_body(&mut self) -> Result<Node, XcliError> {
+        let var = match self.peek() {
+            Some(Token::Word(w)) if parser::is_valid_name(w) => w.clone(),
+            _ => return Err(syntax_error(&format!("{}, variable name expected", self.describe()))),
+        };
+        self.pos += 1;
+        self.expect("in")?;
+
+        let mut words = vec![];
+        while let Some(Token::Word(w)) = self.peek() {
+            words.push(w.clone());
+            self.pos += 1;
+        }
+        self.skip_separators(false);
+        self.expect("do")?;
+        let body = self.list(&["done"])?;
+        self.expect("done")?;
+        Ok(Node::For { var, words, body })
+    }
+}
+
+/// Parse the text of a script.
+fn parse(text: &str) -> Result<List, XcliError> {
+    let mut parser = Parser::new(text)?;
+    let list = parser.list(&[])?;
+    if parser.peek().is_some() {
+        return Err(syntax_error(&parser.describe()));
+    }
+    Ok(list)
+}
+
+/// Whether the result means to exit the CLI.
+fn is_exit(ret: &XcliResult) -> bool {
+    matches!(ret, Ok(CmdExeCode::Exit))
+}
+
+impl Node {
+    fn exec(&self, app: &App) -> XcliResult {
+        match self {
+            Node::Simple(words) => {
+                let ret = match parser::expand_words(app, words) {
+                    Ok(args) if args.is_empty() => Ok(CmdExeCode::Ok),
+                    Ok(args) => app._run(args.iter().map(String::as_str).collect()),
+                    Err(err) => {
+                        app.report_error(&err, None);
+                        app.audit_error(&err);
+                        Err(err)
+                    }
+                };
+                app.set_last_status(&ret);
+                ret
+            }
+            Node::If { cond, then, otherwise } => {
+                let ret = exec_list(app, cond);
+                match ret {
+                    Ok(CmdExeCode::Ok) => exec_list(app, then),
+                    Ok(CmdExeCode::Exit) => ret,
+                    Err(_) => otherwise.as_ref().map_or(Ok(CmdExeCode::Ok), |list| exec_list(app, list)),
+                }
+            }
+            Node::For { var, words, body } => {
+                let words = match parser::expand_words(app, words) {
+                    Ok(words) => words,
+                    Err(err) => {
+                        app.report_error(&err, None);
+                        app.audit_error(&err);
+                        let ret = Err(err);
+                        app.set_last_status(&ret);
+                        return ret;
+                    }
+                };
+                let mut ret = Ok(CmdExeCode::Ok);
+                for word in words {
+                    if app.is_cancelled() {
+                        break;
+                    }
+                    app.set_var(var, word)?;
+                    ret = exec_list(app, body);
+                    if is_exit(&ret) {
+                        break;
+                    }
+                }
+                ret
+            }
+        }
+    }
+}
+
+impl AndOr {
+    fn exec(&self, app: &App) -> XcliResult {
+        let mut ret = self.first.exec(app);
+        for (chain, node) in &self.rest {
+            if is_exit(&ret) {
+                break;
+            }
+            if (*chain == Chain::And) == ret.is_ok() {
+                ret = node.exec(app);
+            }
+        }
+        ret
+    }
+}
+
+/// Submit commands to be run in background.
+fn submit(app: &App, text: &str) -> XcliResult {
+    match app.jobs.submit(text) {
+        Some(id) => {
+            xprintln!("[{}] {}", id, text);
+            Ok(CmdExeCode::Ok)
+        }
+        None => {
+            let err = XcliError::Other("Background jobs are available only in the CLI loop".into());
+            app.report_error(&err, None);
+            Err(err)
+        }
+    }
+}
+
+fn exec_list(app: &App, list: &[Item]) -> XcliResult {
+    let mut ret = Ok(CmdExeCode::Ok);
+    for item in list {
+        if app.is_cancelled() {
+            break;
+        }
+        ret = if item.background {
+            submit(app, &item.text)
+        } else {
+            item.commands.exec(app)
+        };
+        if is_exit(&ret) {
+            break;
+        }
+    }
+    ret
+}
+
+/// Parse and run a script.
+pub(crate) fn run(app: &App, text: &str) -> XcliResult {
+    match parse(text) {
+        Ok(list) => exec_list(app, &list),
+        Err(err) => {
+            app.report_error(&err, None);
+            app.audit_error(&err);
+            Err(err)
+        }
+    }
+}
+
+/// Run a script sourced from a file, which fails if scripts are sourced too deep,
+/// e.g. a script sources itself.
+///
+/// The error returned is reported already, see `take_reported`.
+pub(crate) fn source(app: &App, text: &str) -> XcliResult {
+    let depth = SOURCE_DEPTH.with(Cell::get);
+    let ret = if depth >= MAX_SOURCE_DEPTH {
+        let err = XcliError::Other(format!("Scripts are sourced too deep, {} levels at most", MAX_SOURCE_DEPTH));
+        app.report_error(&err, None);
+        Err(err)
+    } else {
+        SOURCE_DEPTH.with(|d| d.set(depth + 1));
+        let ret = run(app, text);
+        SOURCE_DEPTH.with(|d| d.set(depth));
+        ret
+    };
+    REPORTED.with(|r| r.set(ret.is_err()));
+    ret
+}
+
+/// Whether a script is being sourced on this thread, where the user is not asked.
+pub(crate) fn is_sourcing() -> bool {
+    SOURCE_DEPTH.with(Cell::get) > 0
+}
+
+/// Mark the error about to be returned by a command as reported already,
+/// e.g. it is in the output of a job shown.
+pub(crate) fn set_reported() {
+    REPORTED.with(|r| r.set(true));
+}
+
+/// Whether the error just returned by `source` has been reported, by the
+/// command failed in the script, so that it is not reported again.
+pub(crate) fn take_reported() -> bool {
+    REPORTED.with(|r| r.replace(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output;
+
+    /// Run the script, returns the output with the error messages, if any.
+    fn run_output(app: &App, text: &str) -> String {
+        output::capture(|| run(app, text)).1
+    }
+
+    #[test]
+    fn parse_errors() {
+        for text in ["if echo; then echo", "if echo; fi", "for 1 in a; do echo; done", "for x a", "echo; fi", "&& echo"] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+        assert!(parse("").unwrap().is_empty());
+        assert_eq!(parse("a; b\nc & d").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn background_items() {
+        let list = parse("a && b &\nc").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list[0].background);
+        assert_eq!(list[0].text, "a && b");
+        assert!(!list[1].background);
+    }
+
+    #[test]
+    fn chains() {
+        let app = App::new("test");
+        assert_eq!(run_output(&app, "echo a; echo b"), "a\nb\n");
+        assert_eq!(run_output(&app, "echo a && echo b || echo c"), "a\nb\n");
+        let out = run_output(&app, "log bogus && echo b || echo c");
+        assert!(!out.contains("b\n") && out.ends_with("c\n"), "{}", out);
+        let out = run_output(&app, "bogus && echo x; echo $?");
+        assert!(!out.contains("x\n") && out.ends_with("UnknownCommand\n"), "{}", out);
+    }
+
+    #[test]
+    fn if_and_for() {
+        let app = App::new("test");
+        assert_eq!(run_output(&app, "if echo a; then echo b; else echo c; fi"), "a\nb\n");
+        let out = run_output(&app, "if log bogus; then echo b; elif echo c; then echo d; fi");
+        assert!(out.ends_with("c\nd\n"), "{}", out);
+        let out = run_output(&app, "if bogus; then echo b; else echo c; fi");
+        assert!(!out.contains("b\n") && out.ends_with("c\n"), "{}", out);
+        assert_eq!(run_output(&app, "for x in 1 2; do\n  echo $x\ndone"), "1\n2\n");
+        assert_eq!(app.get_var("x").as_deref(), Some("2"));
+        let out = run_output(&app, "for x in $(bogus); do echo $x; done || echo failed");
+        assert!(out.ends_with("failed\n") && app.get_var("x").as_deref() == Some("2"), "{}", out);
+    }
+
+    #[test]
+    fn keywords_as_words() {
+        let app = App::new("test");
+        assert_eq!(run_output(&app, "echo done fi; if echo then; then echo else; fi"), "done fi\nthen\nelse\n");
+        // a quoted keyword is not a keyword
+        assert!(parse("'fi'").is_ok());
+        assert!(parse("fi").is_err());
+    }
+
+    #[test]
+    fn source_depth() {
+        let app = App::new("test");
+        let file = std::env::temp_dir().join(format!("xcli-source-{}.cli", std::process::id()));
+        std::fs::write(&file, format!("source {}", file.display())).unwrap();
+        let (ret, out) = output::capture(|| source(&app, &format!("source {}", file.display())));
+        std::fs::remove_file(&file).unwrap();
+        assert!(ret.is_err());
+        assert_eq!(out.matches("sourced too deep").count(), 1, "{}", out);
+    }
+}
@@ -0,0 +1,72 @@
+//! The exit status of a session, for `std::process::exit`.
+
+use crate::{CmdExeCode, XcliResult};
+
+/// How a session ended, returned by `App::run`, or got by `App::exit_status`
+/// after `App::run_script`.
+///
+/// ```no_run
+/// # use xcli::*;
+/// let app = App::new("xCLI");
+/// let status = app.run();
+/// std::process::exit(status.code());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub(crate) exited: bool,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) failures: usize,
+    pub(crate) last_error: Option<(i32, String)>,
+}
+
+impl ExitStatus {
+    /// Get the exit code, which is the code given by `exit <code>`, or the
+    /// exit code of the error of the last command, 0 if it succeeded.
+    pub fn code(&self) -> i32 {
+        self.exit_code
+            .or_else(|| self.last_error.as_ref().map(|(code, _)| *code))
+            .unwrap_or(0)
+    }
+
+    /// Whether the exit code is 0.
+    pub fn success(&self) -> bool {
+        self.code() == 0
+    }
+
+    /// Whether the session ended by the `exit` command, otherwise by the end
+    /// of input, e.g. EOF of stdin or the end of a script.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Get the number of command lines failed in the session.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Get the error message of the last command line, if it failed.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_ref().map(|(_, msg)| msg.as_str())
+    }
+
+    /// Update the status with the result of a command line.
+    pub(crate) fn record(&mut self, ret: &XcliResult) {
+        match ret {
+            Ok(CmdExeCode::Ok) => self.last_error = None,
+            Ok(CmdExeCode::Exit) => self.exited = true,
+            Err(err) => {
+                self.failures += 1;
+                self.last_error = Some((err.exit_code(), err.to_string()));
+            }
+        }
+    }
+}
+
+/// Describe the result of a command, e.g. "Ok", or the kind of the error,
+/// for `$?` and the audit log.
+pub(crate) fn describe(ret: &XcliResult) -> String {
+    match ret {
+        Ok(code) => format!("{:?}", code),
+        Err(err) => err.kind().to_string(),
+    }
+}
@@ -0,0 +1,147 @@
+//! The command tree of an app, which can be changed while the CLI is running.
+
+use std::collections::HashMap;
+use std::result::Result as stdResult;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{Command, IAny, PrefixCompleter, PrefixNode, XcliError};
+
+/// A handle to the command tree of an app, got by `App::command_tree`, to add
+/// or remove commands while the CLI is running, e.g. by a thread watching the
+/// devices, as `App::run` takes the app.
+///
+/// Clones of it share the same tree. Commands being executed keep the tree as
+/// it was.
+///
+/// ```no_run
+/// # use xcli::*;
+/// let app = App::new("xCLI");
+/// let tree = app.command_tree();
+/// std::thread::spawn(move || {
+///     // when a device is connected
+///     tree.add_command(&[], Command::new("eth1").about("the device connected")).unwrap();
+/// });
+/// app.run();
+/// ```
+#[derive(Clone)]
+pub struct CommandTree<'a> {
+    root: Arc<RwLock<Arc<Command<'a>>>>,
+    pub(crate) completion: Arc<Mutex<PrefixNode>>,
+    experimental: Arc<AtomicBool>,
+    /// The userdata of the top level commands, by the names.
+    handlers: Arc<Mutex<HashMap<String, Arc<IAny>>>>,
+}
+
+impl<'a> CommandTree<'a> {
+    pub(crate) fn new(root: Command<'a>) -> Self {
+        CommandTree {
+            completion: Arc::new(Mutex::new(PrefixCompleter::build(&root, false))),
+            root: Arc::new(RwLock::new(Arc::new(root))),
+            experimental: Arc::default(),
+            handlers: Arc::default(),
+        }
+    }
+
+    /// Get the tree as it is now.
+    pub(crate) fn get(&self) -> Arc<Command<'a>> {
+        self.root.read().unwrap().clone()
+    }
+
+    /// Change the tree, and update the completion.
+    pub(crate) fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Command<'a>) -> R,
+    {
+        let mut root = self.root.write().unwrap();
+        let ret = f(Arc::make_mut(&mut root));
+        *self.completion.lock().unwrap() = PrefixCompleter::build(&root, self.is_experimental());
+        ret
+    }
+
+    /// Whether the experimental commands are enabled.
+    pub(crate) fn is_experimental(&self) -> bool {
+        self.experimental.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable the experimental commands, and update the completion.
+    pub(crate) fn set_experimental(&self, enabled: bool) {
+        let root = self.root.read().unwrap();
+        self.experimental.store(enabled, Ordering::Relaxed);
+        *self.completion.lock().unwrap() = PrefixCompleter::build(&root, enabled);
+    }
+
+    /// Set the userdata of the top level command of name.
+    pub(crate) fn set_handler(&self, name: &str, value: IAny) {
+        self.handlers.lock().unwrap().insert(name.to_string(), Arc::new(value));
+    }
+
+    /// Get the userdata of the top level command of name.
+    pub(crate) fn get_handler(&self, name: &str) -> Option<Arc<IAny>> {
+        self.handlers.lock().unwrap().get(name).cloned()
+    }
+
+    /// Locate the command by path to be changed, e.g. `["terminal", "length"]`,
+    /// with the names or the aliases.
+    fn locate_mut<'c>(root: &'c mut Command<'a>, path: &[&str]) -> stdResult<&'c mut Command<'a>, XcliError> {
+        let mut cmd = root;
+        for name in path {
+            cmd = cmd
+                .subcommands
+                .iter_mut()
+                .find(|c| c.name == *name || c.alias.as_ref().is_some_and(|a| a == name))
+                .ok_or_else(|| XcliError::BadArgument(format!("{}, no such command", path.join(" "))))?;
+        }
+        Ok(cmd)
+    }
+
+    /// Add a command to the parent command at path, `&[]` for the top level.
+    ///
+    /// It fails if the parent is not found, or the name or the alias of cmd is
+    /// taken by a subcommand of it, as the name or the alias.
+    pub fn add_command(&self, parent: &[&str], cmd: Command<'a>) -> stdResult<(), XcliError> {
+        let names = |c: &Command<'a>| std::iter::once(c.name.clone()).chain(c.alias.clone()).collect::<Vec<_>>();
+        self.update(|root| {
+            let parent = Self::locate_mut(root, parent)?;
+            let new = names(&cmd);
+            if let Some(name) = parent.subcommands.iter().flat_map(names).find(|n| new.contains(n)) {
+                return Err(XcliError::BadArgument(format!("{}, command exists", name)));
+            }
+            parent.subcommands.push(cmd);
+            Ok(())
+        })
+    }
+
+    /// Remove the command at path, with its subcommands, and its userdata if any.
+    pub fn remove_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        let (_, parent) = path
+            .split_last()
+            .ok_or_else(|| XcliError::BadArgument("no command to remove".into()))?;
+        self.update(|root| {
+            let name = Self::locate_mut(root, path)?.name.clone();
+            let parent_cmd = Self::locate_mut(root, parent)?;
+            parent_cmd.subcommands.retain(|c| c.name != name);
+            if parent.is_empty() {
+                self.handlers.lock().unwrap().remove(&name);
+            }
+            Ok(())
+        })
+    }
+
+    /// Enable the command at path, which has been disabled.
+    pub fn enable_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        self.update(|root| {
+            Self::locate_mut(root, path)?.disabled = false;
+            Ok(())
+        })
+    }
+
+    /// Disable the command at path, with its subcommands, which are hidden
+    /// from completion, help and tree, and can't be executed, until enabled again.
+    pub fn disable_command(&self, path: &[&str]) -> stdResult<(), XcliError> {
+        self.update(|root| {
+            Self::locate_mut(root, path)?.disabled = true;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,178 @@
+//! The `watch` builtin, which executes a command periodically.
+
+use std::io::{stdout, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event};
+
+use crate::output;
+use crate::pager::RawMode;
+use crate::{App, CmdExeCode, XcliError, XcliResult};
+
+/// Default interval between two runs, in seconds.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Max interval between two runs, a day.
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Options of the watch command.
+struct WatchOptions<'b> {
+    interval: Duration,
+    count: Option<usize>,
+    command: &'b [&'b str],
+}
+
+impl<'b> WatchOptions<'b> {
+    fn parse(args: &'b [&'b str]) -> Result<Self, XcliError> {
+        let mut interval = DEFAULT_INTERVAL;
+        let mut count = None;
+
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                "-n" | "-c" => {
+                    let value = args.get(i + 1).ok_or(XcliError::MissingArgument)?;
+                    if args[i] == "-n" {
+                        interval = value
+                            .parse::<f64>()
+                            .ok()
+                            .and_then(|v| Duration::try_from_secs_f64(v).ok())
+                            .filter(|d| !d.is_zero() && *d <= MAX_INTERVAL)
+                            .ok_or_else(|| {
+                                let err = format!("{}, invalid interval, up to {} seconds", value, MAX_INTERVAL.as_secs());
+                                XcliError::BadArgument(err)
+                            })?;
+                    } else {
+                        let c = value
+                            .parse::<usize>()
+                            .map_err(|err| XcliError::BadArgument(format!("{}, {}", value, err)))?;
+                        if c == 0 {
+                            return Err(XcliError::BadArgument(format!("{}, count must be 1 at least", value)));
+                        }
+                        count = Some(c);
+                    }
+                    i += 2;
+                }
+                _ => break,
+            }
+        }
+
+        if i == args.len() {
+            return Err(XcliError::MissingArgument);
+        }
+
+        Ok(WatchOptions {
+            interval,
+            count,
+            command: &args[i..],
+        })
+    }
+}
+
+/// Highlight the characters of line which differ from the previous run.
+fn highlight(line: &str, prev: Option<&str>) -> String {
+    let prev = match prev {
+        Some(prev) if prev != line => prev,
+        _ => return line.to_string(),
+    };
+
+    let mut s = String::new();
+    let mut old = prev.chars();
+    let mut changed = false;
+    for c in line.chars() {
+        let diff = old.next() != Some(c);
+        if diff != changed {
+            s.push_str(if diff { "\x1b[7m" } else { "\x1b[0m" });
+            changed = diff;
+        }
+        s.push(c);
+    }
+    if changed {
+        s.push_str("\x1b[0m");
+    }
+    s
+}
+
+/// Clear the screen and draw the output of the latest run.
+fn redraw(opts: &WatchOptions, out: &str, prev: &str) -> std::io::Result<()> {
+    let mut stdout = stdout();
+    write!(
+        stdout,
+        "\x1b[2J\x1b[HEvery {:.1}s: {}    (press any key to stop)\r\n\r\n",
+        opts.interval.as_secs_f64(),
+        opts.command.join(" ")
+    )?;
+
+    let mut old = prev.lines();
+    for line in out.lines() {
+        // in raw mode, '\n' doesn't move the cursor back to the first column
+        write!(stdout, "{}\r\n", highlight(line, old.next()))?;
+    }
+    stdout.flush()
+}
+
+/// Wait for the interval to elapse, returns true if any key is pressed.
+pub(crate) fn key_pressed(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        match event::poll(deadline - now) {
+            Ok(true) => {
+                if let Ok(Event::Key(_)) = event::read() {
+                    return true;
+                }
+            }
+            Ok(false) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Action of watch command
+pub(crate) fn cli_watch(app: &App, args: &[&str]) -> XcliResult {
+    let opts = WatchOptions::parse(args)?;
+
+    // draw on the terminal only when running in foreground,
+    // otherwise, e.g. as a background job, just print the output of each run
+    let interactive = !output::is_capturing() && stdout().is_terminal();
+    let raw = if interactive { RawMode::enable().ok() } else { None };
+    let interactive = raw.is_some();
+
+    let mut prev = String::new();
+    let mut runs = 0;
+    loop {
+        let (ret, out) = output::capture(|| app._run(opts.command.to_vec()));
+        runs += 1;
+
+        if interactive {
+            let _ = redraw(&opts, &out, &prev);
+        } else {
+            xprintln!("Every {:.1}s: {}\n", opts.interval.as_secs_f64(), opts.command.join(" "));
+            xprint!("{}", out);
+        }
+        prev = out;
+
+        if let Ok(CmdExeCode::Exit) = ret {
+            break;
+        }
+        if opts.count.is_some_and(|c| runs >= c) || app.is_cancelled() {
+            break;
+        }
+
+        if interactive {
+            if key_pressed(opts.interval) {
+                break;
+            }
+        } else {
+            std::thread::sleep(opts.interval);
+        }
+    }
+
+    if interactive {
+        print!("\r\n");
+    }
+    Ok(CmdExeCode::Ok)
+}
@@ -0,0 +1,99 @@
+//! Commands defined by `#[derive(XcliCommand)]`.
+
+#![cfg(feature = "derive")]
+
+use xcli::*;
+
+#[derive(XcliCommand)]
+enum Port {
+    /// shows a port
+    Show {
+        /// the port id
+        id: u32,
+        /// shows the counters as well
+        #[flag]
+        verbose: bool,
+        /// the counter to show
+        #[opt]
+        counter: Option<String>,
+    },
+    /// resets the ports
+    Reset { ids: Vec<u32> },
+}
+
+impl Execute for Port {
+    fn execute(self, app: &App) -> XcliResult {
+        let value = match self {
+            Port::Show { id, verbose, counter } => format!("show {} {} {:?}", id, verbose, counter),
+            Port::Reset { ids } => format!("reset {:?}", ids),
+        };
+        app.set_var("port", value)?;
+        Ok(CmdExeCode::Ok)
+    }
+}
+
+#[derive(XcliCommand)]
+enum Device {
+    /// manages the ports
+    Port(Port),
+    /// reboots the device
+    #[xcli(alias = "rb")]
+    Reboot,
+}
+
+impl Execute for Device {
+    fn execute(self, app: &App) -> XcliResult {
+        match self {
+            Device::Port(port) => {
+                app.set_var("device", "port")?;
+                port.execute(app)
+            }
+            Device::Reboot => {
+                app.set_var("device", "reboot")?;
+                Ok(CmdExeCode::Ok)
+            }
+        }
+    }
+}
+
+fn app() -> App<'static> {
+    let mut app = App::new("test");
+    app.add_commands::<Device>();
+    app
+}
+
+#[test]
+fn subcommands_dispatched_by_parent() {
+    let app = app();
+    app.run_script("port show 3 --verbose --counter rx").unwrap();
+    assert_eq!(app.get_var("device").as_deref(), Some("port"));
+    assert_eq!(app.get_var("port").as_deref(), Some("show 3 true Some(\"rx\")"));
+
+    app.run_script("port reset 1 2").unwrap();
+    assert_eq!(app.get_var("port").as_deref(), Some("reset [1, 2]"));
+
+    app.run_script("rb").unwrap();
+    assert_eq!(app.get_var("device").as_deref(), Some("reboot"));
+}
+
+#[test]
+fn bad_arguments() {
+    let app = app();
+    assert!(matches!(app.run_script("port show x"), Err(XcliError::InvalidArgument { .. })));
+    assert!(matches!(app.run_script("port show"), Err(XcliError::RequiredArgument(_))));
+    assert!(matches!(app.run_script("port show 1 2"), Err(XcliError::BadArgument(_))));
+    assert!(matches!(app.run_script("port show 1 --bogus"), Err(XcliError::BadArgument(_))));
+    assert_eq!(app.get_var("port"), None);
+}
+
+#[test]
+fn flags_completed() {
+    let tree = Command::new("").subcommands(Device::commands());
+    let completer = PrefixCompleter::new(&tree);
+    let complete = |line: &str| completer.complete_cmd(line, line.len()).unwrap().1;
+
+    assert_eq!(complete("port sh"), ["ow "]);
+    assert_eq!(complete("port show 1 --v"), ["erbose "]);
+    assert_eq!(complete("port show 1 --verbose "), ["--counter "]);
+    assert!(complete("port reset --").is_empty());
+}
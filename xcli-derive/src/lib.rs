@@ -0,0 +1,422 @@
+//! `#[derive(XcliCommand)]`, which defines xcli commands by types.
+//!
+//! Use it by the `derive` feature of xcli, see the docs of `xcli::XcliCommand`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, Fields, GenericArgument, Ident, Lit, Meta,
+    PathArguments, Result, Type,
+};
+
+/// Derive `xcli::XcliCommand` for an enum, each variant of which is a
+/// command, or for a struct, which is a single command.
+#[proc_macro_derive(XcliCommand, attributes(xcli, arg, flag, opt))]
+pub fn derive_xcli_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Settings given by `#[xcli(...)]` and doc comments.
+#[derive(Default)]
+struct Settings {
+    name: Option<String>,
+    alias: Option<String>,
+    usage: Option<String>,
+    about: Option<String>,
+    long_about: Option<String>,
+}
+
+impl Settings {
+    fn from_attrs(attrs: &[Attribute]) -> Result<Self> {
+        let (about, long_about) = docs(attrs);
+        let mut settings = Settings {
+            about,
+            long_about,
+            ..Settings::default()
+        };
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("xcli")) {
+            attr.parse_nested_meta(|meta| {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                if meta.path.is_ident("name") {
+                    settings.name = Some(value.value());
+                } else if meta.path.is_ident("alias") {
+                    settings.alias = Some(value.value());
+                } else if meta.path.is_ident("usage") {
+                    settings.usage = Some(value.value());
+                } else if meta.path.is_ident("about") {
+                    settings.about = Some(value.value());
+                } else {
+                    return Err(meta.error("unknown xcli attribute, expected name, alias, usage or about"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(settings)
+    }
+}
+
+/// Get the first paragraph of doc comments, and the rest.
+fn docs(attrs: &[Attribute]) -> (Option<String>, Option<String>) {
+    let mut lines = vec![];
+    for attr in attrs.iter().filter(|a| a.path().is_ident("doc")) {
+        if let Meta::NameValue(nv) = &attr.meta {
+            if let Expr::Lit(expr) = &nv.value {
+                if let Lit::Str(s) = &expr.lit {
+                    lines.push(s.value().trim().to_string());
+                }
+            }
+        }
+    }
+
+    // paragraphs are separated by empty lines
+    let mut paragraphs: Vec<String> = vec![];
+    let mut current = String::new();
+    for line in lines {
+        if line.is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+        } else {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(&line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    if paragraphs.is_empty() {
+        return (None, None);
+    }
+    let about = paragraphs.remove(0);
+    let long_about = if paragraphs.is_empty() { None } else { Some(paragraphs.join("\n")) };
+    (Some(about), long_about)
+}
+
+/// Convert an identifier to kebab-case, e.g. ShowPorts to show-ports.
+fn kebab(ident: &Ident) -> String {
+    let mut s = String::new();
+    for (i, c) in ident.to_string().trim_start_matches("r#").chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                s.push('-');
+            }
+            s.extend(c.to_lowercase());
+        } else if c == '_' {
+            s.push('-');
+        } else {
+            s.push(c);
+        }
+    }
+    s
+}
+
+/// How a field is given on the command line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Required,
+    Optional,
+    Rest,
+    Flag,
+    Opt,
+}
+
+/// Get the name of the outermost type, e.g. Option of `Option<T>`.
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(p) => p.path.segments.last().and_then(|seg| match &seg.arguments {
+            PathArguments::AngleBracketed(args) if matches!(args.args.first(), Some(GenericArgument::Type(_))) => {
+                Some(seg.ident.to_string())
+            }
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Whether the type is `bool`.
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(p) if p.qself.is_none() && p.path.is_ident("bool"))
+}
+
+/// A field, aka. an argument of the command.
+struct Arg {
+    ident: Ident,
+    kind: Kind,
+    help: String,
+}
+
+impl Arg {
+    fn new(field: &syn::Field) -> Result<Self> {
+        let ident = field.ident.clone().expect("named field");
+        let is = |name: &str| field.attrs.iter().any(|a| a.path().is_ident(name));
+        let wrapper = type_name(&field.ty);
+
+        let kind = if is("flag") {
+            if !is_bool(&field.ty) {
+                return Err(Error::new_spanned(&field.ty, "#[flag] field must be a bool"));
+            }
+            Kind::Flag
+        } else if is("opt") {
+            if wrapper.as_deref() != Some("Option") {
+                return Err(Error::new_spanned(&field.ty, "#[opt] field must be an Option<T>"));
+            }
+            Kind::Opt
+        } else {
+            match wrapper.as_deref() {
+                Some("Option") => Kind::Optional,
+                Some("Vec") => Kind::Rest,
+                _ => Kind::Required,
+            }
+        };
+
+        let (about, long_about) = docs(&field.attrs);
+        let help = match (about, long_about) {
+            (Some(about), Some(long_about)) => format!("{} {}", about, long_about),
+            (about, _) => about.unwrap_or_default(),
+        };
+        Ok(Arg { ident, kind, help })
+    }
+
+    /// The name of the argument on the command line, e.g. "<id>" or "--dry-run".
+    fn name(&self) -> String {
+        let name = self.ident.to_string().trim_start_matches("r#").to_string();
+        match self.kind {
+            Kind::Required => format!("<{}>", name),
+            Kind::Optional => format!("[{}]", name),
+            Kind::Rest => format!("[{}...]", name),
+            Kind::Flag | Kind::Opt => format!("--{}", name.replace('_', "-")),
+        }
+    }
+
+    /// The text in the usage, e.g. "[--count <count>]".
+    fn usage(&self) -> String {
+        match self.kind {
+            Kind::Flag => format!("[{}]", self.name()),
+            Kind::Opt => format!("[{} <{}>]", self.name(), self.ident.to_string().trim_start_matches("r#")),
+            _ => self.name(),
+        }
+    }
+
+    /// The expression to get the value from `parsed`.
+    fn value(&self) -> TokenStream2 {
+        let ident = &self.ident;
+        let name = self.name();
+        let value = match self.kind {
+            Kind::Required => quote!(parsed.required(#name)?),
+            Kind::Optional => quote!(parsed.optional(#name)?),
+            Kind::Rest => quote!(parsed.rest(#name)?),
+            Kind::Flag => quote!(parsed.flag(#name)),
+            Kind::Opt => quote!(parsed.option(#name)?),
+        };
+        quote!(#ident: #value)
+    }
+}
+
+/// Make the builder expression of a command, with its name and settings.
+fn command(settings: &Settings, default_name: String) -> TokenStream2 {
+    let name = settings.name.clone().unwrap_or(default_name);
+    let mut cmd = match &settings.alias {
+        Some(alias) => quote!(::xcli::Command::new_with_alias(#name, #alias)),
+        None => quote!(::xcli::Command::new(#name)),
+    };
+    if let Some(about) = &settings.about {
+        cmd = quote!(#cmd.about(#about));
+    }
+    if let Some(long_about) = &settings.long_about {
+        cmd = quote!(#cmd.long_about(#long_about));
+    }
+    cmd
+}
+
+/// Make a command which runs an action, constructing the value by path from
+/// the fields, which is executed by the dispatcher `D`.
+fn leaf(settings: &Settings, default_name: String, path: TokenStream2, fields: &Fields) -> Result<TokenStream2> {
+    let name = settings.name.clone().unwrap_or_else(|| default_name.clone());
+    let mut cmd = command(settings, default_name);
+
+    let args = match fields {
+        Fields::Named(named) => named.named.iter().map(Arg::new).collect::<Result<Vec<_>>>()?,
+        Fields::Unit => vec![],
+        Fields::Unnamed(f) => return Err(Error::new_spanned(f, "expected named fields, or a single subcommand type")),
+    };
+
+    let usage = settings.usage.clone().unwrap_or_else(|| {
+        let mut usage = name;
+        for arg in &args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        usage
+    });
+    cmd = quote!(#cmd.usage(#usage));
+
+    for arg in &args {
+        let (name, help) = (arg.name(), &arg.help);
+        cmd = match arg.kind {
+            Kind::Flag => quote!(#cmd.flag(#name, #help)),
+            Kind::Opt => {
+                let name = arg.usage();
+                let name = &name[1..name.len() - 1];
+                quote!(#cmd.flag(#name, #help))
+            }
+            _ => quote!(#cmd.arg(#name, #help)),
+        };
+    }
+
+    let flags: Vec<_> = args.iter().filter(|a| a.kind == Kind::Flag).map(Arg::name).collect();
+    let options: Vec<_> = args.iter().filter(|a| a.kind == Kind::Opt).map(Arg::name).collect();
+    let values: Vec<_> = args.iter().map(Arg::value).collect();
+    let construct = match fields {
+        Fields::Unit => path,
+        _ => quote!(#path { #(#values),* }),
+    };
+
+    Ok(quote! {
+        #cmd.action(|app: &::xcli::App, args: &[&str]| -> ::xcli::XcliResult {
+            #[allow(unused_mut)]
+            let mut parsed = ::xcli::__private::Parsed::new(args, &[#(#flags),*], &[#(#options),*])?;
+            let value = #construct;
+            parsed.finish()?;
+            <D as ::xcli::__private::Dispatch<Self>>::dispatch(value, app)
+        })
+    })
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "XcliCommand can't be derived for generic types"));
+    }
+
+    let ident = &input.ident;
+    // the dispatchers wrapping the values of subcommands into the variants
+    let mut dispatchers = vec![];
+    let commands = match &input.data {
+        Data::Struct(data) => {
+            let settings = Settings::from_attrs(&input.attrs)?;
+            vec![leaf(&settings, kebab(ident), quote!(#ident), &data.fields)?]
+        }
+        Data::Enum(data) => {
+            let mut commands = vec![];
+            for variant in &data.variants {
+                let settings = Settings::from_attrs(&variant.attrs)?;
+                let name = kebab(&variant.ident);
+                let v = &variant.ident;
+                match &variant.fields {
+                    Fields::Unnamed(f) if f.unnamed.len() == 1 => {
+                        // a command with the subcommands of the field type, whose values
+                        // are wrapped in this variant, then executed by D
+                        let ty = &f.unnamed[0].ty;
+                        let dispatcher = format_ident!("__XcliDispatch{}", v);
+                        dispatchers.push(quote! {
+                            struct #dispatcher<D>(::std::marker::PhantomData<D>);
+
+                            impl<D: ::xcli::__private::Dispatch<#ident>> ::xcli::__private::Dispatch<#ty> for #dispatcher<D> {
+                                fn dispatch(value: #ty, app: &::xcli::App) -> ::xcli::XcliResult {
+                                    D::dispatch(#ident::#v(value), app)
+                                }
+                            }
+                        });
+                        let cmd = command(&settings, name);
+                        commands.push(quote! {
+                            #cmd.subcommands(<#ty as ::xcli::XcliCommand>::commands_in::<#dispatcher<D>>())
+                        });
+                    }
+                    fields => commands.push(leaf(&settings, name, quote!(#ident::#v), fields)?),
+                }
+            }
+            commands
+        }
+        Data::Union(_) => {
+            return Err(Error::new(Span::call_site(), "XcliCommand can't be derived for unions"));
+        }
+    };
+
+    Ok(quote! {
+        const _: () = {
+            #(#dispatchers)*
+
+            impl ::xcli::XcliCommand for #ident {
+                fn commands_in<D: ::xcli::__private::Dispatch<Self>>() -> ::std::vec::Vec<::xcli::Command<'static>> {
+                    ::std::vec![#(#commands),*]
+                }
+            }
+        };
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_err(input: DeriveInput) -> String {
+        match expand(&input) {
+            Ok(_) => panic!("expanded unexpectedly"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn expand_dispatchers() {
+        let input: DeriveInput = syn::parse_quote! {
+            enum Device {
+                /// manages the ports
+                Port(Port),
+                Ping { host: String, #[flag] verbose: bool },
+            }
+        };
+        let code = expand(&input).unwrap().to_string();
+        assert!(code.contains("struct __XcliDispatchPort"), "{}", code);
+        assert!(code.contains("D :: dispatch (Device :: Port (value) , app)"), "{}", code);
+        assert!(code.contains("commands_in :: < __XcliDispatchPort < D >>"), "{}", code);
+        assert!(code.contains("\"ping <host> [--verbose]\""), "{}", code);
+        // no closure to silence the dead code warning of the variant
+        assert!(!code.contains("irrefutable_let_patterns"), "{}", code);
+    }
+
+    #[test]
+    fn flag_must_be_bool() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Show { #[flag] verbose: u32 }
+        };
+        assert_eq!(expand_err(input), "#[flag] field must be a bool");
+    }
+
+    #[test]
+    fn opt_must_be_option() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Show { #[opt] count: u32 }
+        };
+        assert_eq!(expand_err(input), "#[opt] field must be an Option<T>");
+    }
+
+    #[test]
+    fn unsupported_types() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Show<T> { value: T }
+        };
+        assert_eq!(expand_err(input), "XcliCommand can't be derived for generic types");
+
+        let input: DeriveInput = syn::parse_quote! {
+            enum Port { Show(u32, u32) }
+        };
+        assert!(expand_err(input).starts_with("expected named fields"));
+
+        let input: DeriveInput = syn::parse_quote! {
+            #[xcli(bogus = "x")]
+            struct Show;
+        };
+        assert!(expand_err(input).starts_with("unknown xcli attribute"));
+    }
+
+    #[test]
+    fn kebab_case() {
+        assert_eq!(kebab(&format_ident!("ShowPorts")), "show-ports");
+        assert_eq!(kebab(&format_ident!("show_ports")), "show-ports");
+    }
+}